@@ -0,0 +1,29 @@
+use amqp_worker::{job::JobResult, MessageError};
+use serde_json::{json, Value};
+
+/// A batch is delivered as a message carrying a JSON list of jobs, instead of a job.
+pub fn get_batch_jobs(data: &str) -> Option<Vec<Value>> {
+  match serde_json::from_str(data) {
+    Ok(Value::Array(jobs)) => Some(jobs),
+    _ => None,
+  }
+}
+
+/// Entry of a job in the batch result, its own result is published like the result of any job.
+pub fn get_batch_result(result: &Result<JobResult, MessageError>) -> Value {
+  match result {
+    Ok(job_result) => json!({
+      "job_id": job_result.get_job_id(),
+      "status": "completed",
+    }),
+    Err(MessageError::ProcessingError(job_result)) => json!({
+      "job_id": job_result.get_job_id(),
+      "status": "error",
+    }),
+    Err(error) => json!({
+      "job_id": null,
+      "status": "error",
+      "message": format!("{:?}", error),
+    }),
+  }
+}
@@ -0,0 +1,137 @@
+use crate::config;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+  static ref SHARED_CACHE: Mutex<LruCache> = Mutex::new(LruCache::new(config::cache_size()));
+}
+
+struct CacheEntry {
+  value: String,
+  expires_at: Option<Instant>,
+  last_used: u64,
+}
+
+/// Least recently used cache with a time to live per entry.
+pub struct LruCache {
+  capacity: usize,
+  entries: HashMap<String, CacheEntry>,
+  clock: u64,
+}
+
+impl LruCache {
+  pub fn new(capacity: usize) -> Self {
+    LruCache {
+      capacity,
+      entries: HashMap::new(),
+      clock: 0,
+    }
+  }
+
+  pub fn get(&mut self, key: &str) -> Option<String> {
+    self.clock += 1;
+    let now = Instant::now();
+
+    let expired = match self.entries.get_mut(key) {
+      Some(entry) => {
+        if entry.expires_at.map(|at| at <= now).unwrap_or(false) {
+          true
+        } else {
+          entry.last_used = self.clock;
+          return Some(entry.value.clone());
+        }
+      }
+      None => false,
+    };
+
+    if expired {
+      self.entries.remove(key);
+    }
+    None
+  }
+
+  /// A `ttl_secs` of 0 keeps the entry until it is evicted.
+  pub fn set(&mut self, key: &str, value: &str, ttl_secs: u64) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    self.clock += 1;
+    let expires_at = if ttl_secs == 0 {
+      None
+    } else {
+      Some(Instant::now() + Duration::from_secs(ttl_secs))
+    };
+
+    if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+      self.evict();
+    }
+
+    self.entries.insert(
+      key.to_string(),
+      CacheEntry {
+        value: value.to_string(),
+        expires_at,
+        last_used: self.clock,
+      },
+    );
+  }
+
+  fn evict(&mut self) {
+    let now = Instant::now();
+    self
+      .entries
+      .retain(|_, entry| entry.expires_at.map(|at| at > now).unwrap_or(true));
+
+    if self.entries.len() < self.capacity {
+      return;
+    }
+
+    let least_recently_used = self
+      .entries
+      .iter()
+      .min_by_key(|(_, entry)| entry.last_used)
+      .map(|(key, _)| key.clone());
+
+    if let Some(key) = least_recently_used {
+      self.entries.remove(&key);
+    }
+  }
+}
+
+pub fn get(key: &str) -> Option<String> {
+  SHARED_CACHE
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .get(key)
+}
+
+pub fn set(key: &str, value: &str, ttl_secs: u64) {
+  SHARED_CACHE
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .set(key, value, ttl_secs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn least_recently_used_entry_is_evicted() {
+    let mut cache = LruCache::new(2);
+    cache.set("first", "1", 0);
+    cache.set("second", "2", 60);
+    assert_eq!(cache.get("first"), Some("1".to_string()));
+
+    cache.set("third", "3", 0);
+    assert_eq!(cache.get("second"), None);
+    assert_eq!(cache.get("first"), Some("1".to_string()));
+    assert_eq!(cache.get("third"), Some("3".to_string()));
+
+    let mut disabled = LruCache::new(0);
+    disabled.set("first", "1", 0);
+    assert_eq!(disabled.get("first"), None);
+  }
+}
@@ -0,0 +1,297 @@
+use crate::{
+  cache, config,
+  helpers::{get_destinations, json_to_py, py_to_json},
+  job_state::{JobDeferral, JobFailure, JobState},
+  log_forwarding, media_info,
+  messages::{self, MessagePublisher},
+  outputs,
+  parameters::ParameterDescription,
+  span_handle::SpanHandle,
+  telemetry,
+};
+use amqp_worker::job::Job;
+use pyo3::{
+  exceptions::{RuntimeError, ValueError},
+  prelude::*,
+  types::{PyAny, PyDict},
+};
+use sha2::{Digest, Sha256};
+use std::{
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+#[pyclass]
+pub struct CallbackHandle {
+  publisher: Arc<dyn MessagePublisher>,
+  job: Job,
+  state: Arc<JobState>,
+  parameters_description: Vec<ParameterDescription>,
+  priority: u8,
+  last_heartbeat: Mutex<Option<Instant>>,
+}
+
+impl CallbackHandle {
+  pub fn new(
+    publisher: Arc<dyn MessagePublisher>,
+    job: Job,
+    state: Arc<JobState>,
+    parameters_description: Vec<ParameterDescription>,
+    priority: u8,
+  ) -> Self {
+    CallbackHandle {
+      publisher,
+      job,
+      state,
+      parameters_description,
+      priority,
+      last_heartbeat: Mutex::new(None),
+    }
+  }
+}
+
+/// The error codes are restricted by `PYTHON_WORKER_ERROR_CODES` when it is set.
+fn check_error_code(code: &str) -> PyResult<()> {
+  if let Some(error_codes) = config::error_codes() {
+    if !error_codes.iter().any(|error_code| error_code == code) {
+      return Err(PyErr::new::<ValueError, _>(format!(
+        "unknown error code {}, expected one of: {}",
+        code,
+        error_codes.join(", ")
+      )));
+    }
+  }
+  Ok(())
+}
+
+impl CallbackHandle {
+  fn publish_work_progression(&self, percentage: Option<u8>) -> bool {
+    match percentage {
+      Some(percentage) if self.state.advance_progression(percentage) => {
+        self.publish_job_progression(percentage)
+      }
+      _ => true,
+    }
+  }
+}
+
+#[pymethods]
+impl CallbackHandle {
+  fn publish_job_progression(&self, value: u8) -> bool {
+    let published = self
+      .publisher
+      .publish_job_progression(&self.job, value)
+      .is_ok();
+    telemetry::add_event(format!("job progression: {}%", value));
+    published
+  }
+
+  /// Total amount of work units of the job, it can be revised while processing.
+  fn set_total_work(&self, total: u64) -> bool {
+    let percentage = self
+      .state
+      .update_work_progress(|work_progress| work_progress.set_total(total));
+    self.publish_work_progression(percentage)
+  }
+
+  fn add_work_done(&self, delta: u64) -> bool {
+    let percentage = self
+      .state
+      .update_work_progress(|work_progress| work_progress.add_done(delta));
+    self.publish_work_progression(percentage)
+  }
+
+  /// Progression counted in frames, samples or bytes, `total` is 0 when it is unknown.
+  fn publish_media_progression(&self, current: u64, total: u64, unit: &str) -> PyResult<bool> {
+    if !["frames", "samples", "bytes"].contains(&unit) {
+      return Err(PyErr::new::<ValueError, _>(format!(
+        "unknown progression unit {}, expected one of: frames, samples, bytes",
+        unit
+      )));
+    }
+
+    if total == 0 {
+      info!("job {}: {} {} processed", self.job.job_id, current, unit);
+      return Ok(true);
+    }
+
+    let percentage = (current.min(total) as f64 * 100.0 / total as f64) as u8;
+    info!(
+      "job {}: {} / {} {} processed ({}%)",
+      self.job.job_id, current, total, unit, percentage
+    );
+    Ok(self.publish_work_progression(Some(percentage)))
+  }
+
+  /// Intermediate result, converted like the dict returned by `process`, which stays the
+  /// authoritative result of the job.
+  fn publish_intermediate_result(&self, result: &PyDict) -> PyResult<bool> {
+    let parameters = outputs::get_output_parameters(result.as_ref(), &config::json_options())
+      .map_err(PyErr::new::<ValueError, _>)?;
+    let destination_paths: Vec<String> = get_destinations(result.as_ref())
+      .unwrap_or_default()
+      .into_iter()
+      .map(|destination| destination.path)
+      .collect();
+
+    let message = serde_json::json!({
+      "job_id": self.job.job_id,
+      "destination_paths": destination_paths,
+      "parameters": parameters,
+    });
+
+    match messages::publish_intermediate_result(self.publisher.as_ref(), message) {
+      Ok(()) => Ok(true),
+      Err(error) => {
+        warn!("unable to publish the intermediate result: {}", error);
+        Ok(false)
+      }
+    }
+  }
+
+  /// Heartbeats sent more often than `PYTHON_WORKER_HEARTBEAT_INTERVAL` are skipped.
+  fn heartbeat(&self) -> bool {
+    let mut last_heartbeat = self
+      .last_heartbeat
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+
+    if let Some(last_heartbeat) = *last_heartbeat {
+      if last_heartbeat.elapsed() < config::heartbeat_interval() {
+        return true;
+      }
+    }
+
+    match messages::publish_job_heartbeat(self.publisher.as_ref(), &self.job) {
+      Ok(()) => {
+        *last_heartbeat = Some(Instant::now());
+        true
+      }
+      Err(error) => {
+        warn!("unable to publish the job heartbeat: {}", error);
+        false
+      }
+    }
+  }
+
+  /// Raises an exception to stop `process`, the job fails with this code even if it is caught.
+  fn fail(&self, code: &str, message: &str) -> PyResult<()> {
+    check_error_code(code)?;
+
+    self.state.set_failure(JobFailure {
+      code: code.to_string(),
+      message: message.to_string(),
+    });
+
+    Err(PyErr::new::<RuntimeError, _>(format!(
+      "job failed with code {}: {}",
+      code, message
+    )))
+  }
+
+  /// Child span of the job span, ended by calling `end` on the returned handle.
+  fn start_span(&self, name: &str) -> PyResult<Py<SpanHandle>> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    Py::new(py, SpanHandle::start(name))
+  }
+
+  /// Deterministic seed of the job, derived from its identifier, for reproducible random processing.
+  fn seed(&self) -> u64 {
+    let digest = Sha256::digest(self.job.job_id.to_string().as_bytes());
+    digest
+      .iter()
+      .take(8)
+      .fold(0, |seed, byte| (seed << 8) | u64::from(*byte))
+  }
+
+  /// Deployment configuration, read from the `PYTHON_WORKER_CFG_<KEY>` environment variables.
+  fn config(&self, key: &str) -> Option<String> {
+    config::worker_configuration(key)
+  }
+
+  /// AMQP priority of the message carrying the job, 0 when it is not given.
+  fn priority(&self) -> u8 {
+    self.priority
+  }
+
+  /// Raises an exception to stop `process`, the job is requeued after the delay instead of failing.
+  fn defer(&self, delay_secs: u64, reason: &str) -> PyResult<()> {
+    self.state.set_deferral(JobDeferral {
+      delay: Duration::from_secs(delay_secs),
+      reason: reason.to_string(),
+    });
+
+    Err(PyErr::new::<RuntimeError, _>(format!(
+      "job deferred for {}s: {}",
+      delay_secs, reason
+    )))
+  }
+
+  /// Records an error which does not fail the job, reported in the `recorded_errors` parameter.
+  fn record_error(&self, code: &str, message: &str) -> PyResult<()> {
+    check_error_code(code)?;
+
+    warn!("job {}: {} ({})", self.job.job_id, message, code);
+    self.state.record_error(JobFailure {
+      code: code.to_string(),
+      message: message.to_string(),
+    });
+    Ok(())
+  }
+
+  fn log(&self, level: &str, message: &str) {
+    let level = log_forwarding::parse_level(level);
+    self.state.write_log(&level.to_string(), message);
+    log_forwarding::forward(level, message);
+  }
+
+  /// Characteristics of the input media (e.g. duration, width, height, codec), added to the job
+  /// result in the `media_info` parameter.
+  fn report_media_info(&self, info: &PyDict) -> PyResult<()> {
+    let info = py_to_json(info.as_ref()).map_err(PyErr::new::<ValueError, _>)?;
+    media_info::check_media_info(&info).map_err(PyErr::new::<ValueError, _>)?;
+    self.state.set_media_info(info);
+    Ok(())
+  }
+
+  /// Annotation of the job, added to the job result in the `annotations` parameter.
+  fn set_annotation(&self, key: &str, value: &PyAny) -> PyResult<()> {
+    let value = py_to_json(value).map_err(PyErr::new::<ValueError, _>)?;
+    self.state.set_annotation(key, value);
+    Ok(())
+  }
+
+  fn get_annotation(&self, key: &str) -> PyObject {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    self
+      .state
+      .get_annotation(key)
+      .map(|value| json_to_py(py, &value))
+      .unwrap_or_else(|| py.None())
+  }
+
+  /// Parameters declared by `get_parameters`, as a list of dicts.
+  fn get_schema(&self) -> PyObject {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    let schema = self
+      .parameters_description
+      .iter()
+      .map(ParameterDescription::to_json)
+      .collect();
+    json_to_py(py, &serde_json::Value::Array(schema))
+  }
+
+  fn cache_get(&self, key: &str) -> Option<String> {
+    cache::get(key)
+  }
+
+  fn cache_set(&self, key: &str, value: &str, ttl_secs: u64) {
+    cache::set(key, value, ttl_secs)
+  }
+}
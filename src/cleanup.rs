@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Removes the files listed by the worker, only when they are located under one of the allowed roots.
+pub fn remove_paths(paths: &[String], roots: &[PathBuf]) {
+  if paths.is_empty() {
+    return;
+  }
+
+  if roots.is_empty() {
+    warn!(
+      "no cleanup root configured (PYTHON_WORKER_CLEANUP_ROOTS), keep {:?}",
+      paths
+    );
+    return;
+  }
+
+  let roots: Vec<PathBuf> = roots
+    .iter()
+    .filter_map(|root| root.canonicalize().ok())
+    .collect();
+
+  for path in paths {
+    let canonical_path = match Path::new(path).canonicalize() {
+      Ok(canonical_path) => canonical_path,
+      Err(error) => {
+        warn!("unable to cleanup {}: {}", path, error);
+        continue;
+      }
+    };
+
+    // a root itself is never removed, only what it contains
+    if !roots
+      .iter()
+      .any(|root| canonical_path != *root && canonical_path.starts_with(root))
+    {
+      warn!("refuse to cleanup {}: outside of the cleanup roots", path);
+      continue;
+    }
+
+    let removed = if canonical_path.is_dir() {
+      fs::remove_dir_all(&canonical_path)
+    } else {
+      fs::remove_file(&canonical_path)
+    };
+
+    match removed {
+      Ok(()) => info!("cleanup: removed {}", canonical_path.display()),
+      Err(error) => warn!("unable to cleanup {}: {}", path, error),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn remove_paths_keeps_the_root_and_removes_its_descendants() {
+    let root = std::env::temp_dir().join(format!("py_amqp_worker_cleanup_{}", std::process::id()));
+    let file = root.join("output.txt");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(&file, "output").unwrap();
+    let roots = vec![root.clone()];
+
+    remove_paths(&[root.to_string_lossy().to_string()], &roots);
+    assert!(root.is_dir());
+    assert!(file.is_file());
+
+    remove_paths(&[file.to_string_lossy().to_string()], &roots);
+    assert!(root.is_dir());
+    assert!(!file.exists());
+
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
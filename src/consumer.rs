@@ -0,0 +1,844 @@
+use crate::{
+  batch, cleanup, config, failure, health,
+  job_state::JobDeferral,
+  messages::{self, ChannelPublisher, MessagePublisher},
+  parameters::ParameterDescription,
+  webhook, PythonWorkerEvent,
+};
+use amqp_worker::{
+  job::{Job, JobResult, JobStatus},
+  worker::WorkerConfiguration,
+  MessageError,
+};
+use futures::{Future, Stream};
+use lapin_futures::{
+  message::Delivery,
+  options::*,
+  types::{AMQPValue, FieldTable},
+  uri::{AMQPAuthority, AMQPScheme, AMQPUri, AMQPUserInfo},
+  BasicProperties, Channel, Client, ConnectionProperties, Error, ExchangeKind,
+};
+use serde_json::json;
+use std::{
+  env,
+  sync::{Arc, Mutex},
+  thread,
+  time::Duration,
+};
+
+const EXCHANGE_NAME_SUBMIT: &str = "job_submit";
+const EXCHANGE_NAME_RESPONSE: &str = "job_response";
+const EXCHANGE_NAME_DELAYED: &str = "job_delayed";
+const EXCHANGE_NAME_RESPONSE_DELAYED: &str = "job_response_delayed";
+const QUEUE_NAME_WORKER_DISCOVERY: &str = "worker_discovery";
+
+/// What to do with a delivery once its result is handled.
+#[derive(Debug, PartialEq)]
+pub enum Acknowledgement {
+  Ack,
+  Reject { requeue: bool },
+}
+
+/// Properties of the delivery carrying the job, which are not part of the job message.
+#[derive(Debug, Default)]
+pub struct DeliveryInfo {
+  pub priority: u8,
+  /// 1 for the first delivery, increased each time the job was requeued through the delayed queue.
+  pub attempt: i64,
+  /// The job is one of the jobs of a batch, it cannot be deferred.
+  pub batch_item: bool,
+  deferral: Mutex<Option<JobDeferral>>,
+  cleanup_paths: Mutex<Vec<String>>,
+}
+
+impl DeliveryInfo {
+  fn new(delivery: &Delivery) -> Self {
+    let death_count = delivery
+      .properties
+      .headers()
+      .as_ref()
+      .and_then(|headers| match headers.inner().get("x-death") {
+        Some(AMQPValue::FieldArray(deaths)) => deaths.as_slice().first().cloned(),
+        _ => None,
+      })
+      .and_then(|death| match death {
+        AMQPValue::FieldTable(death) => match death.inner().get("count") {
+          Some(AMQPValue::LongLongInt(count)) => Some(*count),
+          _ => None,
+        },
+        _ => None,
+      })
+      .unwrap_or(0);
+
+    DeliveryInfo {
+      priority: delivery.properties.priority().unwrap_or(0),
+      attempt: death_count + 1,
+      batch_item: false,
+      deferral: Mutex::new(None),
+      cleanup_paths: Mutex::new(vec![]),
+    }
+  }
+
+  /// Each job of a batch has its own delivery, so it does not share the state of the other jobs.
+  fn get_batch_item(&self) -> Self {
+    DeliveryInfo {
+      priority: self.priority,
+      attempt: self.attempt,
+      batch_item: true,
+      ..Default::default()
+    }
+  }
+
+  /// The message is published to the deferred queue instead of publishing a result.
+  pub fn defer(&self, deferral: JobDeferral) {
+    *self
+      .deferral
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some(deferral);
+  }
+
+  fn take_deferral(&self) -> Option<JobDeferral> {
+    self
+      .deferral
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .take()
+  }
+
+  /// The paths are removed once the result is published, a requeued job still finds its inputs.
+  pub fn cleanup_after_ack(&self, paths: Vec<String>) {
+    self
+      .cleanup_paths
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .extend(paths);
+  }
+
+  fn take_cleanup_paths(&self) -> Vec<String> {
+    std::mem::take(
+      &mut *self
+        .cleanup_paths
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()),
+    )
+  }
+}
+
+/// Queue holding the deferred jobs until their expiration, then dead-lettering them to the work queue.
+fn get_deferred_queue_name(queue_name: &str) -> String {
+  format!("{}_deferred", queue_name)
+}
+
+pub fn get_amqp_uri() -> AMQPUri {
+  let scheme = if config::amqp_tls() {
+    AMQPScheme::AMQPS
+  } else {
+    AMQPScheme::AMQP
+  };
+
+  AMQPUri {
+    scheme,
+    authority: AMQPAuthority {
+      userinfo: AMQPUserInfo {
+        username: config::amqp_username(),
+        password: config::amqp_password(),
+      },
+      host: config::amqp_hostname(),
+      port: config::amqp_port(),
+    },
+    vhost: config::amqp_vhost(),
+    query: Default::default(),
+  }
+}
+
+pub fn connect() -> Result<Client, Box<Error>> {
+  Ok(Client::connect_uri(get_amqp_uri(), ConnectionProperties::default()).wait()?)
+}
+
+/// Declares the exchanges and queues of the AMQP worker, and returns the queue to consume.
+fn declare(channel: &Channel, queue_name: &str) -> Result<lapin_futures::Queue, Box<Error>> {
+  let exchange_options = ExchangeDeclareOptions {
+    durable: true,
+    ..Default::default()
+  };
+  let mut submit_arguments = FieldTable::default();
+  submit_arguments.insert(
+    "alternate-exchange".into(),
+    AMQPValue::LongString("job_queue_not_found".into()),
+  );
+  let mut response_arguments = FieldTable::default();
+  response_arguments.insert(
+    "alternate-exchange".into(),
+    AMQPValue::LongString("job_response_not_found".into()),
+  );
+
+  for (exchange, kind, arguments) in [
+    (
+      EXCHANGE_NAME_DELAYED,
+      ExchangeKind::Fanout,
+      FieldTable::default(),
+    ),
+    (EXCHANGE_NAME_SUBMIT, ExchangeKind::Topic, submit_arguments),
+    (
+      EXCHANGE_NAME_RESPONSE,
+      ExchangeKind::Topic,
+      response_arguments,
+    ),
+  ] {
+    if let Err(error) = channel
+      .exchange_declare(exchange, kind, exchange_options.clone(), arguments)
+      .wait()
+    {
+      error!("Unable to create exchange {}: {:?}", exchange, error);
+    }
+  }
+
+  let mut delayed_arguments = FieldTable::default();
+  delayed_arguments.insert(
+    "x-dead-letter-exchange".into(),
+    AMQPValue::LongString("".into()),
+  );
+  delayed_arguments.insert("x-message-ttl".into(), AMQPValue::ShortInt(5000));
+  if let Err(error) = channel
+    .queue_declare(
+      EXCHANGE_NAME_DELAYED,
+      QueueDeclareOptions::default(),
+      delayed_arguments,
+    )
+    .wait()
+  {
+    error!(
+      "Unable to create queue {}: {:?}",
+      EXCHANGE_NAME_DELAYED, error
+    );
+  }
+  if let Err(error) = channel
+    .queue_bind(
+      EXCHANGE_NAME_DELAYED,
+      EXCHANGE_NAME_DELAYED,
+      "*",
+      QueueBindOptions::default(),
+      FieldTable::default(),
+    )
+    .wait()
+  {
+    error!(
+      "Unable to bind queue {}: {:?}",
+      EXCHANGE_NAME_DELAYED, error
+    );
+  }
+
+  let mut discovery_arguments = FieldTable::default();
+  discovery_arguments.insert(
+    "x-dead-letter-exchange".into(),
+    AMQPValue::LongString(EXCHANGE_NAME_RESPONSE_DELAYED.into()),
+  );
+  discovery_arguments.insert(
+    "x-dead-letter-routing-key".into(),
+    AMQPValue::LongString(QUEUE_NAME_WORKER_DISCOVERY.into()),
+  );
+  let durable = QueueDeclareOptions {
+    durable: true,
+    ..Default::default()
+  };
+  channel
+    .queue_declare(
+      QUEUE_NAME_WORKER_DISCOVERY,
+      durable.clone(),
+      discovery_arguments,
+    )
+    .wait()?;
+
+  let mut queue_arguments = FieldTable::default();
+  queue_arguments.insert(
+    "x-dead-letter-exchange".into(),
+    AMQPValue::LongString(EXCHANGE_NAME_DELAYED.into()),
+  );
+  queue_arguments.insert(
+    "x-dead-letter-routing-key".into(),
+    AMQPValue::LongString(queue_name.into()),
+  );
+  queue_arguments.insert("x-max-priority".into(), AMQPValue::ShortInt(100));
+  let queue = channel
+    .queue_declare(queue_name, durable.clone(), queue_arguments)
+    .wait()?;
+  info!("channel {} declared queue {}", channel.id(), queue_name);
+
+  let mut deferred_arguments = FieldTable::default();
+  deferred_arguments.insert(
+    "x-dead-letter-exchange".into(),
+    AMQPValue::LongString("".into()),
+  );
+  deferred_arguments.insert(
+    "x-dead-letter-routing-key".into(),
+    AMQPValue::LongString(queue_name.into()),
+  );
+  channel
+    .queue_declare(
+      &get_deferred_queue_name(queue_name),
+      durable.clone(),
+      deferred_arguments,
+    )
+    .wait()?;
+
+  if let Some(dead_letter_queue) = config::dead_letter_queue() {
+    channel
+      .queue_declare(&dead_letter_queue, durable, FieldTable::default())
+      .wait()?;
+  }
+
+  if let Err(error) = channel
+    .queue_bind(
+      queue_name,
+      EXCHANGE_NAME_SUBMIT,
+      queue_name,
+      QueueBindOptions::default(),
+      FieldTable::default(),
+    )
+    .wait()
+  {
+    error!(
+      "Unable to bind queue to exchange {}: {:?}",
+      EXCHANGE_NAME_SUBMIT, error
+    );
+  }
+
+  Ok(queue)
+}
+
+fn publish_worker_definition(
+  channel: &Channel,
+  event: &'static PythonWorkerEvent,
+  queue_name: &str,
+) -> Result<(), Box<Error>> {
+  let mut worker_definition = json!(WorkerConfiguration::new(queue_name, event));
+  worker_definition["parameters"] = event
+    .get_parameters_description()
+    .iter()
+    .map(ParameterDescription::to_worker_parameter_json)
+    .collect();
+  channel
+    .basic_publish(
+      "",
+      QUEUE_NAME_WORKER_DISCOVERY,
+      worker_definition.to_string().into_bytes(),
+      BasicPublishOptions::default(),
+      BasicProperties::default(),
+    )
+    .wait()?;
+  Ok(())
+}
+
+fn acknowledge(channel: &Channel, delivery: &Delivery, acknowledgement: Acknowledgement) {
+  let result = match acknowledgement {
+    Acknowledgement::Ack => channel.basic_ack(delivery.delivery_tag, false).wait(),
+    Acknowledgement::Reject { requeue } => channel
+      .basic_reject(delivery.delivery_tag, BasicRejectOptions { requeue })
+      .wait(),
+  };
+  if let Err(error) = result {
+    error!("Unable to acknowledge message {:?}", error);
+  }
+}
+
+/// Publishes the result of the job, the message is requeued when the result cannot be published.
+fn publish_result(
+  publisher: &dyn MessagePublisher,
+  result: &Result<JobResult, MessageError>,
+) -> Acknowledgement {
+  let published = match result {
+    Ok(job_result) => {
+      info!(target: &job_result.get_str_job_id(), "Completed");
+      messages::publish_job_result(
+        publisher,
+        "job_completed",
+        json!(job_result).to_string(),
+        config::compress_threshold(),
+      )
+    }
+    Err(MessageError::ProcessingError(job_result)) => {
+      error!(target: &job_result.get_str_job_id(), "Job returned in error: {:?}", job_result.get_parameters());
+      let content = json!(JobResult::new(job_result.get_job_id())
+        .with_status(JobStatus::Error)
+        .with_parameters(&mut job_result.get_parameters().clone()));
+      messages::publish_job_result(
+        publisher,
+        "job_error",
+        content.to_string(),
+        config::compress_threshold(),
+      )
+    }
+    Err(MessageError::RuntimeError(message)) => {
+      error!("An error occurred: {:?}", message);
+      let content = json!({
+        "status": "error",
+        "message": message,
+      });
+      publisher.publish("job_error", content.to_string())
+    }
+    Err(MessageError::RequirementsError(details)) => {
+      debug!("{}", details);
+      return Acknowledgement::Reject { requeue: false };
+    }
+    Err(MessageError::NotImplemented()) => {
+      error!("Not implemented feature");
+      return Acknowledgement::Reject { requeue: true };
+    }
+  };
+
+  match published {
+    Ok(()) => Acknowledgement::Ack,
+    Err(error) => {
+      error!("unable to publish the job result: {}", error);
+      Acknowledgement::Reject { requeue: true }
+    }
+  }
+}
+
+/// Processes a job message, or each job of a batch message, and publishes the results, like the
+/// AMQP worker.
+pub fn handle_message(
+  event: &PythonWorkerEvent,
+  publisher: &Arc<dyn MessagePublisher>,
+  delivery: &DeliveryInfo,
+  data: &str,
+) -> Acknowledgement {
+  match batch::get_batch_jobs(data) {
+    Some(jobs) if !delivery.batch_item => handle_batch(event, publisher, delivery, jobs),
+    _ => handle_job(event, publisher, delivery, data).0,
+  }
+}
+
+/// The message is requeued when a result cannot be published, so the jobs of the batch which
+/// are already completed can be processed again.
+fn handle_batch(
+  event: &PythonWorkerEvent,
+  publisher: &Arc<dyn MessagePublisher>,
+  delivery: &DeliveryInfo,
+  jobs: Vec<serde_json::Value>,
+) -> Acknowledgement {
+  let mut batch_results = vec![];
+  let mut items = vec![];
+  let mut failures = 0;
+  let mut requeue = false;
+
+  for job in &jobs {
+    let item_delivery = delivery.get_batch_item();
+    let (acknowledgement, result) = handle_job(event, publisher, &item_delivery, &job.to_string());
+    if acknowledgement == (Acknowledgement::Reject { requeue: true }) {
+      requeue = true;
+    }
+    if result.is_err() {
+      failures += 1;
+    }
+    batch_results.push(batch::get_batch_result(&result));
+    items.push(item_delivery);
+  }
+
+  info!("batch of {} jobs, {} failed", jobs.len(), failures);
+  let batch_result = json!({
+    "jobs": batch_results,
+    "failures": failures,
+  });
+  if let Err(error) = publisher.publish("job_batch_completed", batch_result.to_string()) {
+    error!("unable to publish the batch result: {}", error);
+    requeue = true;
+  }
+
+  if requeue {
+    return Acknowledgement::Reject { requeue: true };
+  }
+  for item in items {
+    cleanup::remove_paths(&item.take_cleanup_paths(), &config::cleanup_roots());
+  }
+  Acknowledgement::Ack
+}
+
+fn handle_job(
+  event: &PythonWorkerEvent,
+  publisher: &Arc<dyn MessagePublisher>,
+  delivery: &DeliveryInfo,
+  data: &str,
+) -> (Acknowledgement, Result<JobResult, MessageError>) {
+  let result = if batch::get_batch_jobs(data).is_some() {
+    Err(MessageError::RuntimeError(
+      "a batch cannot contain another batch".to_string(),
+    ))
+  } else {
+    Job::new(data)
+  };
+  let result = result.and_then(|job| {
+    debug!(target: &job.job_id.to_string(), "received message: {:?}", job);
+    job.check_requirements()?;
+
+    if delivery.batch_item && !event.has_capability("batch") {
+      let result = JobResult::new(job.job_id)
+        .with_status(JobStatus::Error)
+        .with_message(
+          "batch jobs are not supported, the worker does not declare the batch capability",
+        );
+      return Err(MessageError::ProcessingError(result));
+    }
+
+    publisher
+      .publish_job_progression(&job, 0)
+      .map_err(|error| {
+        let result = JobResult::new(job.job_id)
+          .with_status(JobStatus::Error)
+          .with_message(&error);
+        MessageError::ProcessingError(result)
+      })?;
+
+    event.process_job(
+      publisher.clone(),
+      &job,
+      JobResult::new(job.job_id),
+      delivery,
+    )
+  });
+
+  if let Some(deferral) = delivery.take_deferral() {
+    return (
+      publish_deferred(publisher.as_ref(), &deferral, data),
+      result,
+    );
+  }
+  if let Ok(_) | Err(MessageError::ProcessingError(_)) = result {
+    health::increment_processed_jobs();
+  }
+
+  let acknowledgement = publish_result(publisher.as_ref(), &result);
+  // the batch removes the paths of its jobs once all their results are published
+  if acknowledgement == Acknowledgement::Ack && !delivery.batch_item {
+    cleanup::remove_paths(&delivery.take_cleanup_paths(), &config::cleanup_roots());
+  }
+  // a requeued job has no result yet, it is posted once processed again
+  if let (Acknowledgement::Ack, Some(url)) = (&acknowledgement, config::completion_webhook()) {
+    webhook::send_completion(url, &result);
+  }
+
+  let acknowledgement = match (acknowledgement, config::dead_letter_queue()) {
+    (Acknowledgement::Ack, Some(dead_letter_queue)) => publish_dead_letter(
+      event,
+      publisher.as_ref(),
+      &dead_letter_queue,
+      delivery,
+      data,
+      &result,
+    ),
+    (acknowledgement, _) => acknowledgement,
+  };
+  (acknowledgement, result)
+}
+
+/// The message expires from the deferred queue after the delay, and is dead-lettered to the work queue.
+fn publish_deferred(
+  publisher: &dyn MessagePublisher,
+  deferral: &JobDeferral,
+  data: &str,
+) -> Acknowledgement {
+  let deferred_queue = get_deferred_queue_name(&config::queue_name());
+  let expiration = deferral.delay.as_millis().to_string();
+  let properties = BasicProperties::default().with_expiration(expiration.into());
+
+  match publisher.publish_to_queue(&deferred_queue, data.to_string(), properties) {
+    Ok(()) => Acknowledgement::Ack,
+    Err(error) => {
+      error!("unable to defer the job to {}: {}", deferred_queue, error);
+      Acknowledgement::Reject { requeue: true }
+    }
+  }
+}
+
+/// Republishes the message of a failed job to the dead-letter queue, with the failure as headers.
+fn publish_dead_letter(
+  event: &PythonWorkerEvent,
+  publisher: &dyn MessagePublisher,
+  dead_letter_queue: &str,
+  delivery: &DeliveryInfo,
+  data: &str,
+  result: &Result<JobResult, MessageError>,
+) -> Acknowledgement {
+  let job_result = match result {
+    Err(MessageError::ProcessingError(job_result)) => job_result.clone(),
+    Err(MessageError::RuntimeError(message)) => failure::with_failure_context(
+      JobResult::new(0).with_message(message),
+      event,
+      "RuntimeError",
+      message,
+    ),
+    _ => return Acknowledgement::Ack,
+  };
+
+  let headers = failure::get_dead_letter_headers(&job_result, event, delivery.attempt);
+  let properties = BasicProperties::default().with_headers(headers);
+  match publisher.publish_to_queue(dead_letter_queue, data.to_string(), properties) {
+    Ok(()) => Acknowledgement::Ack,
+    Err(error) => {
+      error!(
+        "unable to publish the failed job to {}: {}",
+        dead_letter_queue, error
+      );
+      Acknowledgement::Reject { requeue: true }
+    }
+  }
+}
+
+fn consume(event: &'static PythonWorkerEvent, queue_name: &str) -> Result<(), Box<Error>> {
+  info!("Start connection with configuration:");
+  info!("AMQP TLS: {}", config::amqp_tls());
+  info!("AMQP HOSTNAME: {}", config::amqp_hostname());
+  info!("AMQP PORT: {}", config::amqp_port());
+  info!("AMQP USERNAME: {}", config::amqp_username());
+  info!("AMQP VHOST: {}", config::amqp_vhost());
+  info!("AMQP QUEUE: {}", queue_name);
+
+  let client = connect()?;
+  let channel = client.create_channel().wait()?;
+  debug!("created channel with id: {}", channel.id());
+  if let Err(error) = channel.basic_qos(1, BasicQosOptions::default()).wait() {
+    error!("Unable to set QoS on channels: {:?}", error);
+  }
+
+  let queue = declare(&channel, queue_name)?;
+  publish_worker_definition(&channel, event, queue_name)?;
+
+  // the results are published on their own channel, which can be in confirm mode
+  let publisher: Arc<dyn MessagePublisher> =
+    Arc::new(ChannelPublisher::new(client.create_channel().wait()?));
+
+  let stream = channel
+    .basic_consume(
+      &queue,
+      "amqp_worker",
+      BasicConsumeOptions::default(),
+      FieldTable::default(),
+    )
+    .wait()?;
+  warn!("start listening stream");
+
+  for delivery in stream.wait() {
+    let delivery = delivery?;
+    trace!("raw message: {:?}", delivery);
+    let acknowledgement = match std::str::from_utf8(&delivery.data) {
+      Ok(data) => handle_message(event, &publisher, &DeliveryInfo::new(&delivery), data),
+      Err(error) => {
+        error!("the message is not valid UTF-8: {}", error);
+        Acknowledgement::Reject { requeue: false }
+      }
+    };
+    acknowledge(&channel, &delivery, acknowledgement);
+  }
+  Ok(())
+}
+
+/// Consumes the jobs of the `AMQP_QUEUE` queue, connecting again when the connection is lost.
+pub fn start_worker(event: &'static PythonWorkerEvent) {
+  let queue_name = config::queue_name();
+  let version = env::var("VERSION").unwrap_or_else(|_| "unknown".to_string());
+  info!("Worker: {}, version: {}", queue_name, version);
+
+  loop {
+    if let Err(error) = consume(event, &queue_name) {
+      warn!("{:?}", error);
+    }
+    thread::sleep(Duration::from_secs(1));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{messages::MemoryPublisher, PYTHON_WORKER_EVENT};
+  use serde_json::Value;
+
+  #[test]
+  fn handle_message_publishes_the_progressions_and_the_result() {
+    let memory_publisher = Arc::new(MemoryPublisher::default());
+    let publisher: Arc<dyn MessagePublisher> = memory_publisher.clone();
+    let message = json!({
+      "job_id": 211,
+      "parameters": [
+        {"id": "my_parameter", "type": "string", "value": "my value"}
+      ]
+    });
+
+    let acknowledgement = handle_message(
+      &PYTHON_WORKER_EVENT,
+      &publisher,
+      &DeliveryInfo::default(),
+      &message.to_string(),
+    );
+    assert_eq!(acknowledgement, Acknowledgement::Ack);
+
+    let messages = memory_publisher.messages();
+    let routing_keys: Vec<&str> = messages
+      .iter()
+      .map(|published| published.routing_key.as_str())
+      .collect();
+    assert_eq!(
+      routing_keys,
+      vec!["job_progression", "job_progression", "job_completed"]
+    );
+
+    let progression: Value = serde_json::from_str(&messages[1].message()).unwrap();
+    assert_eq!(progression["job_id"], 211);
+    assert_eq!(progression["progression"], 50);
+
+    let result: Value = serde_json::from_str(&messages[2].message()).unwrap();
+    assert_eq!(result["job_id"], 211);
+    assert_eq!(result["status"], "completed");
+    assert_eq!(
+      result["destination_paths"],
+      json!(["/path/to/generated/file.ext"])
+    );
+  }
+
+  #[test]
+  fn batch_reports_each_job_and_rejects_the_deferral() {
+    let memory_publisher = Arc::new(MemoryPublisher::default());
+    let publisher: Arc<dyn MessagePublisher> = memory_publisher.clone();
+    let message = json!([
+      {
+        "job_id": 221,
+        "parameters": [{"id": "my_parameter", "type": "string", "value": "my value"}]
+      },
+      {
+        "job_id": 222,
+        "parameters": [{"id": "my_parameter", "type": "string", "value": "not ready"}]
+      },
+      [{"job_id": 223, "parameters": []}]
+    ]);
+
+    let acknowledgement = handle_message(
+      &PYTHON_WORKER_EVENT,
+      &publisher,
+      &DeliveryInfo::default(),
+      &message.to_string(),
+    );
+    assert_eq!(acknowledgement, Acknowledgement::Ack);
+
+    let messages = memory_publisher.messages();
+    assert!(messages
+      .iter()
+      .all(|published| !published.routing_key.ends_with("_deferred")));
+    let results: Vec<(&str, Value)> = messages
+      .iter()
+      .filter(|published| published.routing_key != "job_progression")
+      .map(|published| {
+        (
+          published.routing_key.as_str(),
+          serde_json::from_str(&published.message()).unwrap(),
+        )
+      })
+      .collect();
+    assert_eq!(results.len(), 4, "{:?}", results);
+
+    assert_eq!(results[0].0, "job_completed");
+    assert_eq!(results[0].1["job_id"], 221);
+    assert_eq!(results[1].0, "job_error");
+    assert_eq!(results[1].1["job_id"], 222);
+    assert!(results[1].1["parameters"]
+      .to_string()
+      .contains("a job of a batch cannot be deferred"));
+    assert_eq!(results[2].0, "job_error");
+    assert_eq!(
+      results[2].1["message"],
+      "a batch cannot contain another batch"
+    );
+
+    assert_eq!(results[3].0, "job_batch_completed");
+    assert_eq!(results[3].1["failures"], 2);
+    assert_eq!(results[3].1["jobs"][0]["status"], "completed");
+    assert_eq!(results[3].1["jobs"][1]["status"], "error");
+  }
+
+  #[test]
+  fn deferred_job_expires_after_the_delay() {
+    let publisher = MemoryPublisher::default();
+    let deferral = JobDeferral {
+      delay: Duration::from_secs(30),
+      reason: "not ready".to_string(),
+    };
+
+    let acknowledgement = publish_deferred(&publisher, &deferral, "{}");
+    assert_eq!(acknowledgement, Acknowledgement::Ack);
+
+    let messages = publisher.messages();
+    assert_eq!(
+      messages[0].routing_key,
+      get_deferred_queue_name(&config::queue_name())
+    );
+    assert_eq!(messages[0].message(), "{}");
+    assert_eq!(messages[0].properties.expiration(), &Some("30000".into()));
+  }
+
+  struct FailingPublisher;
+
+  impl MessagePublisher for FailingPublisher {
+    fn publish_response(
+      &self,
+      _routing_key: &str,
+      _payload: Vec<u8>,
+      _properties: BasicProperties,
+    ) -> Result<(), String> {
+      Err("connection lost".to_string())
+    }
+
+    fn publish_to_queue(
+      &self,
+      _queue: &str,
+      _message: String,
+      _properties: BasicProperties,
+    ) -> Result<(), String> {
+      Err("connection lost".to_string())
+    }
+  }
+
+  #[test]
+  fn cleanup_paths_are_removed_once_the_result_is_published() {
+    let root = std::env::temp_dir().join(format!("py_amqp_worker_inputs_{}", std::process::id()));
+    let input = root.join("input.mxf");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(&input, "input").unwrap();
+    env::set_var("PYTHON_WORKER_CLEANUP_ROOTS", &root);
+    let message = json!({"job_id": 206, "parameters": []}).to_string();
+
+    let failing_publisher: Arc<dyn MessagePublisher> = Arc::new(FailingPublisher);
+    let delivery = DeliveryInfo::default();
+    delivery.cleanup_after_ack(vec![input.to_string_lossy().to_string()]);
+    let acknowledgement = handle_message(
+      &PYTHON_WORKER_EVENT,
+      &failing_publisher,
+      &delivery,
+      &message,
+    );
+    assert_eq!(acknowledgement, Acknowledgement::Reject { requeue: true });
+    assert!(input.is_file());
+
+    let publisher: Arc<dyn MessagePublisher> = Arc::new(MemoryPublisher::default());
+    let delivery = DeliveryInfo::default();
+    delivery.cleanup_after_ack(vec![input.to_string_lossy().to_string()]);
+    let acknowledgement = handle_message(&PYTHON_WORKER_EVENT, &publisher, &delivery, &message);
+    assert_eq!(acknowledgement, Acknowledgement::Ack);
+    assert!(!input.exists());
+
+    env::remove_var("PYTHON_WORKER_CLEANUP_ROOTS");
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn handle_message_rejects_an_invalid_message() {
+    let memory_publisher = Arc::new(MemoryPublisher::default());
+    let publisher: Arc<dyn MessagePublisher> = memory_publisher.clone();
+
+    let acknowledgement = handle_message(
+      &PYTHON_WORKER_EVENT,
+      &publisher,
+      &DeliveryInfo::default(),
+      "not a job",
+    );
+    assert_eq!(acknowledgement, Acknowledgement::Ack);
+    assert_eq!(memory_publisher.messages()[0].routing_key, "job_error");
+  }
+}
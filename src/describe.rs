@@ -0,0 +1,87 @@
+use crate::{config, PythonWorkerEvent};
+use amqp_worker::MessageEvent;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Short hash of the Python source, identifying exactly which code runs the jobs.
+pub fn get_source_hash(contents: &str) -> String {
+  let digest = format!("{:x}", Sha256::digest(contents.as_bytes()));
+  digest[..12].to_string()
+}
+
+pub fn get_source_version(event: &PythonWorkerEvent) -> Value {
+  json!({
+    "source_hash": get_source_hash(&event.read_python_file()),
+    "git_sha": config::git_sha(),
+  })
+}
+
+pub fn get_descriptor(event: &PythonWorkerEvent) -> Value {
+  let parameters: Vec<Value> = event
+    .get_parameters_description()
+    .iter()
+    .map(|parameter| parameter.to_json())
+    .collect();
+
+  json!({
+    "name": event.get_name(),
+    "short_description": event.get_short_description(),
+    "description": event.get_description(),
+    "version": event.get_version().to_string(),
+    "parameters": parameters,
+    "capabilities": event.get_capabilities(),
+    "source": get_source_version(event),
+  })
+}
+
+/// Differences between the declared parameters and an expected schema, either a `--describe`
+/// output or a list of parameters.
+pub fn get_schema_differences(event: &PythonWorkerEvent, expected: &Value) -> Vec<String> {
+  let expected = expected
+    .get("parameters")
+    .unwrap_or(expected)
+    .as_array()
+    .cloned()
+    .unwrap_or_default();
+  let actual: Vec<Value> = event
+    .get_parameters_description()
+    .iter()
+    .map(|parameter| parameter.to_json())
+    .collect();
+
+  let find = |parameters: &[Value], identifier: &Value| {
+    parameters
+      .iter()
+      .find(|parameter| parameter.get("identifier") == Some(identifier))
+      .cloned()
+  };
+
+  let mut differences = vec![];
+  for expected_parameter in &expected {
+    let identifier = expected_parameter.get("identifier").unwrap_or(&Value::Null);
+    match find(&actual, identifier) {
+      None => differences.push(format!("parameter {} was removed", identifier)),
+      Some(actual_parameter) => {
+        let fields = expected_parameter.as_object().cloned().unwrap_or_default();
+        for (field, expected_value) in fields {
+          let actual_value = actual_parameter.get(&field).unwrap_or(&Value::Null);
+          if *actual_value != expected_value {
+            differences.push(format!(
+              "parameter {}: {} changed from {} to {}",
+              identifier, field, expected_value, actual_value
+            ));
+          }
+        }
+      }
+    }
+  }
+
+  for actual_parameter in &actual {
+    let identifier = &actual_parameter["identifier"];
+    if find(&expected, identifier).is_none() {
+      differences.push(format!("parameter {} was added", identifier));
+    }
+  }
+
+  differences
+}
@@ -0,0 +1,41 @@
+use crate::{
+  config,
+  helpers::{map_job_result, with_json_parameter},
+  telemetry,
+};
+use amqp_worker::{
+  job::{Job, JobResult},
+  MessageError,
+};
+use serde_json::json;
+use std::time::Duration;
+
+/// Warns about the jobs slower than `PYTHON_WORKER_SLOW_JOB_SECONDS`, even when they succeed,
+/// and annotates their result with the `slow_job` parameter.
+pub fn check_slow_job(
+  job: &Job,
+  elapsed: Duration,
+  result: Result<JobResult, MessageError>,
+) -> Result<JobResult, MessageError> {
+  let threshold = match config::slow_job_threshold() {
+    Some(threshold) if elapsed > threshold => threshold,
+    _ => return result,
+  };
+
+  warn!(
+    "slow job {}: processed in {:.1}s, above the {}s threshold",
+    job.job_id,
+    elapsed.as_secs_f64(),
+    threshold.as_secs()
+  );
+  telemetry::add_event(format!("slow job: {:.1}s", elapsed.as_secs_f64()));
+
+  let annotation = json!({
+    "elapsed_seconds": elapsed.as_secs_f64(),
+    "threshold_seconds": threshold.as_secs(),
+  });
+
+  map_job_result(result, |job_result| {
+    with_json_parameter(job_result, "slow_job", &annotation)
+  })
+}
@@ -0,0 +1,121 @@
+use crate::{config, describe, helpers::with_json_parameter, PythonWorkerEvent};
+use amqp_worker::{job::JobResult, MessageEvent};
+use lapin_futures::types::{AMQPValue, FieldTable};
+use serde_json::{json, Value};
+
+const FAILURE_CONTEXT_PARAMETER: &str = "failure_context";
+
+/// Adds the context of a failure to the job result, for the consumers of failed jobs.
+/// It is also given to the dead-letter queue, when `PYTHON_WORKER_DEAD_LETTER_QUEUE` is set.
+pub fn with_failure_context(
+  job_result: JobResult,
+  event: &PythonWorkerEvent,
+  error_type: &str,
+  message: &str,
+) -> JobResult {
+  if !config::failure_context() && config::dead_letter_queue().is_none() {
+    return job_result;
+  }
+
+  let context = json!({
+    "error_type": error_type,
+    "message": message,
+    "worker_name": event.get_name(),
+    "worker_version": event.get_version().to_string(),
+    "source": describe::get_source_version(event),
+  });
+
+  with_json_parameter(job_result, FAILURE_CONTEXT_PARAMETER, &context)
+}
+
+fn get_string_parameter(job_result: &JobResult, id: &str) -> Option<String> {
+  serde_json::to_value(job_result).ok()?["parameters"]
+    .as_array()?
+    .iter()
+    .find(|parameter| parameter["id"] == id)
+    .and_then(|parameter| parameter["value"].as_str())
+    .map(|value| value.to_string())
+}
+
+/// Headers of a failed job message republished to the dead-letter queue.
+/// The attempt is 1 for the first delivery of the job, and grows each time the job is requeued.
+pub fn get_dead_letter_headers(
+  job_result: &JobResult,
+  event: &PythonWorkerEvent,
+  attempt: i64,
+) -> FieldTable {
+  let context = get_string_parameter(job_result, FAILURE_CONTEXT_PARAMETER)
+    .and_then(|context| serde_json::from_str::<Value>(&context).ok())
+    .unwrap_or(Value::Null);
+  let error_type = context["error_type"]
+    .as_str()
+    .unwrap_or("ProcessingError")
+    .to_string();
+  let message = context["message"]
+    .as_str()
+    .map(|message| message.to_string())
+    .or_else(|| get_string_parameter(job_result, "message"))
+    .unwrap_or_default();
+
+  let mut headers = FieldTable::default();
+  for (key, value) in [
+    ("x-failure-error-type", error_type),
+    ("x-failure-message", message),
+    ("x-failure-worker-name", event.get_name()),
+    ("x-failure-worker-version", event.get_version().to_string()),
+  ] {
+    headers.insert(key.into(), AMQPValue::LongString(value.into()));
+  }
+  headers.insert("x-failure-attempt".into(), AMQPValue::LongLongInt(attempt));
+  headers
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{helpers::with_string_parameter, PYTHON_WORKER_EVENT};
+  use amqp_worker::job::JobStatus;
+
+  #[test]
+  fn dead_letter_headers_give_the_failure_and_the_attempt() {
+    let context = json!({"error_type": "ValueError", "message": "bad input"});
+    let job_result = JobResult::new(227).with_status(JobStatus::Error);
+    let job_result = with_json_parameter(job_result, FAILURE_CONTEXT_PARAMETER, &context);
+
+    let headers = get_dead_letter_headers(&job_result, &PYTHON_WORKER_EVENT, 3);
+    let headers = headers.inner();
+    assert_eq!(
+      headers.get("x-failure-error-type"),
+      Some(&AMQPValue::LongString("ValueError".into()))
+    );
+    assert_eq!(
+      headers.get("x-failure-message"),
+      Some(&AMQPValue::LongString("bad input".into()))
+    );
+    assert_eq!(
+      headers.get("x-failure-worker-version"),
+      Some(&AMQPValue::LongString("0.0.3".into()))
+    );
+    assert_eq!(
+      headers.get("x-failure-attempt"),
+      Some(&AMQPValue::LongLongInt(3))
+    );
+  }
+
+  #[test]
+  fn dead_letter_headers_default_to_the_result_message() {
+    let job_result = JobResult::new(227).with_status(JobStatus::Error);
+    let job_result = with_string_parameter(job_result, "message", "failed");
+
+    let headers = get_dead_letter_headers(&job_result, &PYTHON_WORKER_EVENT, 1);
+    let headers = headers.inner();
+    assert_eq!(
+      headers.get("x-failure-error-type"),
+      Some(&AMQPValue::LongString("ProcessingError".into()))
+    );
+    assert_eq!(
+      headers.get("x-failure-message"),
+      Some(&AMQPValue::LongString("failed".into()))
+    );
+  }
+}
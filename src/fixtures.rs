@@ -0,0 +1,107 @@
+use crate::{
+  helpers::{get_parameter_value, get_result_json, py_to_json},
+  parameters::{find_description, ParameterDescription},
+  secret::SecretValues,
+};
+use amqp_worker::{
+  job::{Job, JobResult},
+  MessageError,
+};
+use pyo3::types::PyDict;
+use serde_json::{json, Map, Value};
+use std::{fs, path::Path};
+
+/// Parameters as resolved for `process`, a typed array keeps the base64 string given by the job.
+pub fn get_fixture_parameters(
+  job: &Job,
+  parameters: &PyDict,
+  descriptions: &[ParameterDescription],
+) -> Value {
+  let mut fixture_parameters = Map::new();
+
+  for (identifier, value) in parameters.iter() {
+    let identifier = identifier.to_string();
+    let is_typed_array = find_description(descriptions, &identifier)
+      .map(|description| description.has_kind("typed_array"))
+      .unwrap_or(false);
+
+    let value = if is_typed_array {
+      get_parameter_value(job, &identifier).map(Value::String)
+    } else {
+      py_to_json(value)
+        .map_err(|error| {
+          warn!(
+            "unable to serialize the parameter {} of the fixture: {}",
+            identifier, error
+          )
+        })
+        .ok()
+    };
+    fixture_parameters.insert(identifier, value.unwrap_or(Value::Null));
+  }
+
+  Value::Object(fixture_parameters)
+}
+
+/// Writes the resolved parameters and the result of a job, to replay it later.
+pub fn write_fixture(
+  directory: &Path,
+  job: &Job,
+  parameters: Option<Value>,
+  result: &Result<JobResult, MessageError>,
+  secret_values: &SecretValues,
+) {
+  let fixture = json!({
+    "job_id": job.job_id,
+    "parameters": parameters,
+    "result": get_result_json(result),
+  });
+
+  let contents = secret_values.redact(&serde_json::to_string_pretty(&fixture).unwrap());
+  let path = directory.join(format!("job_{}.json", job.job_id));
+
+  if let Err(error) = fs::create_dir_all(directory).and_then(|()| fs::write(&path, contents)) {
+    warn!("unable to write the fixture {}: {}", path.display(), error);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::kinds;
+  use pyo3::prelude::*;
+
+  #[test]
+  fn typed_arrays_keep_their_base64_string() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let job = Job::new(
+      &json!({
+        "job_id": 223,
+        "parameters": [
+          {"id": "samples", "type": "string", "value": "AACAPwAAAEA="},
+          {"id": "name", "type": "string", "value": "clip"}
+        ]
+      })
+      .to_string(),
+    )
+    .unwrap();
+    let descriptions = vec![ParameterDescription {
+      identifier: "samples".to_string(),
+      label: "Samples".to_string(),
+      kind: vec!["typed_array".to_string()],
+      dtype: Some("f32".to_string()),
+      ..Default::default()
+    }];
+
+    let parameters = PyDict::new(py);
+    let samples = kinds::convert(py, descriptions.first(), &json!("AACAPwAAAEA=")).unwrap();
+    parameters.set_item("samples", samples).unwrap();
+    parameters.set_item("name", "clip").unwrap();
+
+    assert_eq!(
+      get_fixture_parameters(&job, parameters, &descriptions),
+      json!({"samples": "AACAPwAAAEA=", "name": "clip"})
+    );
+  }
+}
@@ -0,0 +1,96 @@
+use serde_json::json;
+use std::{
+  io::{Read, Write},
+  net::{TcpListener, TcpStream},
+  sync::atomic::{AtomicU64, Ordering},
+  thread,
+  time::Instant,
+};
+
+lazy_static! {
+  static ref STARTED: Instant = Instant::now();
+}
+
+static PROCESSED_JOBS: AtomicU64 = AtomicU64::new(0);
+static QUEUED_JOBS: AtomicU64 = AtomicU64::new(0);
+static IN_FLIGHT_JOBS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a job while it is alive, either waiting for its turn or processed.
+pub struct JobCountGuard {
+  counter: &'static AtomicU64,
+}
+
+impl Drop for JobCountGuard {
+  fn drop(&mut self) {
+    self.counter.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+pub fn queue_job() -> JobCountGuard {
+  QUEUED_JOBS.fetch_add(1, Ordering::Relaxed);
+  JobCountGuard {
+    counter: &QUEUED_JOBS,
+  }
+}
+
+pub fn start_job() -> JobCountGuard {
+  IN_FLIGHT_JOBS.fetch_add(1, Ordering::Relaxed);
+  JobCountGuard {
+    counter: &IN_FLIGHT_JOBS,
+  }
+}
+
+pub fn increment_processed_jobs() {
+  PROCESSED_JOBS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn get_status() -> serde_json::Value {
+  json!({
+    "status": "ok",
+    "uptime_seconds": STARTED.elapsed().as_secs(),
+    "processed_jobs": PROCESSED_JOBS.load(Ordering::Relaxed),
+    "queued_jobs": QUEUED_JOBS.load(Ordering::Relaxed),
+    "in_flight_jobs": IN_FLIGHT_JOBS.load(Ordering::Relaxed),
+  })
+}
+
+fn respond(mut stream: TcpStream) {
+  // the request is not routed, any path returns the status
+  let mut request = [0; 1024];
+  let _ = stream.read(&mut request);
+
+  let body = get_status().to_string();
+  let response = format!(
+    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    body.len(),
+    body
+  );
+  if let Err(error) = stream.write_all(response.as_bytes()) {
+    debug!("unable to answer the health request: {}", error);
+  }
+}
+
+/// Serves the uptime and the number of processed, queued and in flight jobs as JSON, on a dedicated thread.
+pub fn start_endpoint(port: u16) {
+  lazy_static::initialize(&STARTED);
+
+  let listener = match TcpListener::bind(("0.0.0.0", port)) {
+    Ok(listener) => listener,
+    Err(error) => {
+      error!(
+        "unable to start the health endpoint on port {}: {}",
+        port, error
+      );
+      return;
+    }
+  };
+
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      match stream {
+        Ok(stream) => respond(stream),
+        Err(error) => debug!("health connection failed: {}", error),
+      }
+    }
+  });
+}
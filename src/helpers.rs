@@ -1,6 +1,31 @@
+use crate::config;
+use amqp_worker::{
+  job::{Job, JobResult},
+  MessageError,
+  Parameter::*,
+};
 use pyo3::{prelude::*, types::*};
+use serde_json::{Map, Number, Value};
+use std::path::Path;
 
-pub fn get_destination_paths(response: &PyAny) -> Option<Vec<String>> {
+/// Destination path returned by `process`, either as a string or as a `{"path", "mime_type"}` dict.
+pub struct Destination {
+  pub path: String,
+  pub mime_type: Option<String>,
+}
+
+/// Relative paths are resolved against `PYTHON_WORKER_OUTPUT_DIR` when it is set, instead of the
+/// current directory.
+fn resolve_destination_path(path: String) -> String {
+  match config::output_directory() {
+    Some(output_directory) if Path::new(&path).is_relative() => {
+      output_directory.join(path).to_string_lossy().to_string()
+    }
+    _ => path,
+  }
+}
+
+pub fn get_destinations(response: &PyAny) -> Option<Vec<Destination>> {
   if response.is_none() {
     return None;
   }
@@ -10,24 +35,231 @@ pub fn get_destination_paths(response: &PyAny) -> Option<Vec<String>> {
     .map(|object| {
       object
         .get_item("destination_paths")
-        .map(|response_paths| {
+        .and_then(|response_paths| {
           response_paths
             .downcast_ref::<PyList>()
             .map(|path_list| {
-              let mut destination_paths: Vec<String> = vec![];
+              let mut destinations: Vec<Destination> = vec![];
 
-              for path in path_list.iter() {
-                if let Ok(value) = path.downcast_ref::<PyString>() {
-                  if let Ok(string_value) = value.to_string() {
-                    destination_paths.push(string_value.to_string());
+              for item in path_list.iter() {
+                if let Ok(value) = item.downcast_ref::<PyString>() {
+                  if let Ok(path) = value.to_string() {
+                    destinations.push(Destination {
+                      path: resolve_destination_path(path.to_string()),
+                      mime_type: None,
+                    });
+                  }
+                } else if let Ok(entry) = item.downcast_ref::<PyDict>() {
+                  if let Some(path) = entry.get_item("path") {
+                    destinations.push(Destination {
+                      path: resolve_destination_path(path.to_string()),
+                      mime_type: entry
+                        .get_item("mime_type")
+                        .filter(|mime_type| !mime_type.is_none())
+                        .map(|mime_type| mime_type.to_string()),
+                    });
                   }
                 }
               }
-              Some(destination_paths)
+              Some(destinations)
             })
             .unwrap_or(None)
         })
-        .flatten()
     })
     .unwrap_or(None)
 }
+
+pub fn get_string_list(response: &PyAny, key: &str) -> Option<Vec<String>> {
+  if response.is_none() {
+    return None;
+  }
+
+  response
+    .downcast_ref::<PyDict>()
+    .map(|object| {
+      object.get_item(key).and_then(|response_items| {
+        response_items
+          .downcast_ref::<PyList>()
+          .map(|item_list| {
+            let mut items: Vec<String> = vec![];
+
+            for item in item_list.iter() {
+              if let Ok(value) = item.downcast_ref::<PyString>() {
+                if let Ok(string_value) = value.to_string() {
+                  items.push(string_value.to_string());
+                }
+              }
+            }
+            Some(items)
+          })
+          .unwrap_or(None)
+      })
+    })
+    .unwrap_or(None)
+}
+
+pub fn get_missing_paths(paths: &[String]) -> Vec<String> {
+  paths
+    .iter()
+    .filter(|path| !Path::new(path).exists())
+    .cloned()
+    .collect()
+}
+
+/// Value (or default) of a scalar job parameter, formatted as a string.
+pub fn get_parameter_value(job: &Job, identifier: &str) -> Option<String> {
+  job.parameters.iter().find_map(|parameter| match parameter {
+    StringParam { id, default, value } if id == identifier => {
+      value.clone().or_else(|| default.clone())
+    }
+    IntegerParam { id, default, value } if id == identifier => {
+      value.or(*default).map(|value| value.to_string())
+    }
+    BooleanParam { id, default, value } if id == identifier => {
+      value.or(*default).map(|value| value.to_string())
+    }
+    _ => None,
+  })
+}
+
+pub fn with_string_parameter(job_result: JobResult, id: &str, value: &str) -> JobResult {
+  job_result.with_parameters(&mut vec![StringParam {
+    id: id.to_string(),
+    default: None,
+    value: Some(value.to_string()),
+  }])
+}
+
+/// Attaches structured metadata to the job result, serialized as a JSON string parameter.
+pub fn with_json_parameter(
+  job_result: JobResult,
+  id: &str,
+  value: &serde_json::Value,
+) -> JobResult {
+  with_string_parameter(job_result, id, &value.to_string())
+}
+
+pub fn json_to_py(py: Python, value: &Value) -> PyObject {
+  match value {
+    Value::Null => py.None(),
+    Value::Bool(value) => value.to_object(py),
+    Value::Number(number) => match number.as_i64() {
+      Some(value) => value.to_object(py),
+      None => number.as_f64().unwrap_or_default().to_object(py),
+    },
+    Value::String(value) => value.to_object(py),
+    Value::Array(items) => {
+      let list = PyList::empty(py);
+      for item in items {
+        list.append(json_to_py(py, item)).unwrap();
+      }
+      list.to_object(py)
+    }
+    Value::Object(map) => {
+      let dict = PyDict::new(py);
+      for (key, item) in map {
+        dict.set_item(key, json_to_py(py, item)).unwrap();
+      }
+      dict.to_object(py)
+    }
+  }
+}
+
+/// Serialization of the Python values which have no exact JSON representation.
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+  /// Serialize NaN and infinite floats as null, instead of rejecting them.
+  pub non_finite_as_null: bool,
+  /// Number of decimals kept for floats.
+  pub float_precision: Option<i32>,
+  /// Serialize the integers out of the 64 bits range as strings, instead of rejecting them.
+  pub large_integers_as_strings: bool,
+}
+
+/// Converts a Python value returned by the worker to JSON.
+pub fn py_to_json(object: &PyAny) -> Result<Value, String> {
+  py_to_json_with_options(object, &JsonOptions::default())
+}
+
+pub fn py_to_json_with_options(object: &PyAny, options: &JsonOptions) -> Result<Value, String> {
+  if object.is_none() {
+    return Ok(Value::Null);
+  }
+  if let Ok(value) = object.downcast_ref::<PyBool>() {
+    return Ok(Value::Bool(value.is_true()));
+  }
+  if let Ok(value) = object.downcast_ref::<PyLong>() {
+    return match value.extract::<i64>() {
+      Ok(value) => Ok(Value::from(value)),
+      Err(_) if options.large_integers_as_strings => Ok(Value::String(value.to_string())),
+      Err(_) => Err(format!("integer {} is out of range", value)),
+    };
+  }
+  if let Ok(value) = object.downcast_ref::<PyFloat>() {
+    let mut value = value.value();
+    if let Some(precision) = options.float_precision {
+      let factor = 10f64.powi(precision);
+      value = (value * factor).round() / factor;
+    }
+    return match Number::from_f64(value) {
+      Some(number) => Ok(Value::Number(number)),
+      None if options.non_finite_as_null => Ok(Value::Null),
+      None => Err(format!("float {} is not finite", value)),
+    };
+  }
+  if let Ok(value) = object.downcast_ref::<PyString>() {
+    return value
+      .to_string()
+      .map(|value| Value::String(value.to_string()))
+      .map_err(|_| "invalid string".to_string());
+  }
+  if let Ok(list) = object.downcast_ref::<PyList>() {
+    return list
+      .iter()
+      .map(|item| py_to_json_with_options(item, options))
+      .collect::<Result<_, _>>()
+      .map(Value::Array);
+  }
+  if let Ok(tuple) = object.downcast_ref::<PyTuple>() {
+    return tuple
+      .iter()
+      .map(|item| py_to_json_with_options(item, options))
+      .collect::<Result<_, _>>()
+      .map(Value::Array);
+  }
+  if let Ok(dict) = object.downcast_ref::<PyDict>() {
+    let mut map = Map::new();
+    for (key, value) in dict.iter() {
+      map.insert(key.to_string(), py_to_json_with_options(value, options)?);
+    }
+    return Ok(Value::Object(map));
+  }
+
+  Err(format!(
+    "unsupported python type: {}",
+    object.get_type().name()
+  ))
+}
+
+/// Applies the update to the job result, whether the job completed or failed.
+pub fn map_job_result<F: FnOnce(JobResult) -> JobResult>(
+  result: Result<JobResult, MessageError>,
+  update: F,
+) -> Result<JobResult, MessageError> {
+  match result {
+    Ok(job_result) => Ok(update(job_result)),
+    Err(MessageError::ProcessingError(job_result)) => {
+      Err(MessageError::ProcessingError(update(job_result)))
+    }
+    Err(error) => Err(error),
+  }
+}
+
+pub fn get_result_json(result: &Result<JobResult, MessageError>) -> Value {
+  match result {
+    Ok(job_result) | Err(MessageError::ProcessingError(job_result)) => {
+      serde_json::to_value(job_result).unwrap_or(Value::Null)
+    }
+    Err(error) => Value::String(format!("{:?}", error)),
+  }
+}
@@ -0,0 +1,180 @@
+use crate::config;
+use sha2::{Digest, Sha256};
+use std::{
+  cell::RefCell,
+  fs, io,
+  path::Path,
+  sync::{Arc, Mutex},
+};
+
+lazy_static! {
+  static ref INPUT_RESOLVERS: Mutex<Vec<Arc<dyn InputResolver>>> = Mutex::new(vec![]);
+}
+
+thread_local! {
+  static DOWNLOADED_INPUTS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Fetches an input given as a URI (e.g. `https://host/file.mp4`) to a local path.
+pub trait InputResolver: Send + Sync {
+  fn handles(&self, uri: &str) -> bool;
+  fn resolve(&self, uri: &str, directory: &Path) -> Result<String, String>;
+
+  /// Whether the resolved path is a local copy, removed once the job is processed.
+  fn is_local_copy(&self) -> bool {
+    false
+  }
+}
+
+/// `file://` URIs and plain paths are used as they are.
+pub struct FileResolver {}
+
+impl InputResolver for FileResolver {
+  fn handles(&self, uri: &str) -> bool {
+    uri.starts_with("file://") || !uri.contains("://")
+  }
+
+  fn resolve(&self, uri: &str, _directory: &Path) -> Result<String, String> {
+    Ok(uri.trim_start_matches("file://").to_string())
+  }
+}
+
+/// Downloads `http://` and `https://` URIs to the input directory.
+pub struct HttpResolver {}
+
+impl InputResolver for HttpResolver {
+  fn handles(&self, uri: &str) -> bool {
+    uri.starts_with("http://") || uri.starts_with("https://")
+  }
+
+  fn resolve(&self, uri: &str, directory: &Path) -> Result<String, String> {
+    // the file name is kept for the workers relying on the extension
+    let name = uri
+      .split(['?', '#'])
+      .next()
+      .and_then(|uri| uri.rsplit('/').next())
+      .filter(|name| !name.is_empty())
+      .unwrap_or("input");
+    let digest = format!("{:x}", Sha256::digest(uri.as_bytes()));
+    let path = directory.join(format!("{}_{}", &digest[..12], name));
+
+    let mut response = reqwest::get(uri)
+      .and_then(|response| response.error_for_status())
+      .map_err(|error| format!("unable to download {}: {}", uri, error))?;
+
+    fs::create_dir_all(directory)
+      .and_then(|()| fs::File::create(&path))
+      .and_then(|mut file| io::copy(&mut response, &mut file))
+      .map_err(|error| format!("unable to write {}: {}", path.display(), error))?;
+
+    Ok(path.to_string_lossy().to_string())
+  }
+
+  fn is_local_copy(&self) -> bool {
+    true
+  }
+}
+
+/// Resolvers are tried in the order they are registered, new URI schemes are supported by
+/// registering a resolver.
+pub fn register_resolver(resolver: Arc<dyn InputResolver>) {
+  INPUT_RESOLVERS
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .push(resolver);
+}
+
+pub fn resolve_input(uri: &str) -> Result<String, String> {
+  // the lock is not held while resolving, as downloads can be slow
+  let resolver = INPUT_RESOLVERS
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .iter()
+    .find(|resolver| resolver.handles(uri))
+    .cloned()
+    .ok_or_else(|| format!("no resolver for input {}", uri))?;
+
+  let path = resolver.resolve(uri, &config::input_directory())?;
+  if resolver.is_local_copy() {
+    DOWNLOADED_INPUTS.with(|downloaded| downloaded.borrow_mut().push(path.clone()));
+  }
+  Ok(path)
+}
+
+/// Removes the local copies of the inputs resolved by the current thread when dropped.
+pub struct DownloadedInputsGuard {
+  /// Inputs resolved before the guard, e.g. by the batch, which are removed by their own guard.
+  start: usize,
+}
+
+impl Drop for DownloadedInputsGuard {
+  fn drop(&mut self) {
+    let paths = DOWNLOADED_INPUTS.with(|downloaded| {
+      let mut downloaded = downloaded.borrow_mut();
+      let start = self.start.min(downloaded.len());
+      downloaded.split_off(start)
+    });
+
+    for path in paths {
+      match fs::remove_file(&path) {
+        Ok(()) => debug!("removed the downloaded input {}", path),
+        Err(error) => warn!("unable to remove the downloaded input {}: {}", path, error),
+      }
+    }
+  }
+}
+
+/// The inputs downloaded by the current thread until the guard is dropped are removed with it.
+pub fn remove_downloaded_inputs() -> DownloadedInputsGuard {
+  DownloadedInputsGuard {
+    start: DOWNLOADED_INPUTS.with(|downloaded| downloaded.borrow().len()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Copies a local file, like a download.
+  struct CopyResolver {}
+
+  impl InputResolver for CopyResolver {
+    fn handles(&self, uri: &str) -> bool {
+      uri.starts_with("copy://")
+    }
+
+    fn resolve(&self, uri: &str, directory: &Path) -> Result<String, String> {
+      let source = uri.trim_start_matches("copy://");
+      let path = directory.join("copied_input");
+      fs::create_dir_all(directory)
+        .and_then(|()| fs::copy(source, &path))
+        .map_err(|error| error.to_string())?;
+      Ok(path.to_string_lossy().to_string())
+    }
+
+    fn is_local_copy(&self) -> bool {
+      true
+    }
+  }
+
+  #[test]
+  fn local_copies_are_removed_with_the_guard() {
+    register_resolver(Arc::new(CopyResolver {}));
+    register_resolver(Arc::new(FileResolver {}));
+    let source = std::env::temp_dir().join(format!("py_amqp_worker_source_{}", std::process::id()));
+    fs::write(&source, "input").unwrap();
+
+    let (copy, original) = {
+      let _guard = remove_downloaded_inputs();
+      let copy = resolve_input(&format!("copy://{}", source.display())).unwrap();
+      let original = resolve_input(&format!("file://{}", source.display())).unwrap();
+      assert!(Path::new(&copy).is_file());
+      (copy, original)
+    };
+
+    assert!(!Path::new(&copy).exists());
+    assert!(Path::new(&original).is_file());
+    assert_eq!(original, source.to_string_lossy());
+    fs::remove_file(&source).unwrap();
+  }
+}
@@ -0,0 +1,138 @@
+use crate::{config, describe};
+use pyo3::{
+  exceptions::TypeError,
+  prelude::*,
+  types::{PyAny, PyDict},
+};
+use std::sync::Mutex;
+
+lazy_static! {
+  static ref CACHED_MODULE: Mutex<Option<(String, PyObject)>> = Mutex::new(None);
+}
+
+/// Applies the interpreter settings, once before running any worker code.
+pub fn configure() {
+  let gil = Python::acquire_gil();
+  let py = gil.python();
+
+  let sys = py.import("sys").expect("unable to import sys");
+
+  if let Some(recursion_limit) = config::recursion_limit() {
+    sys
+      .call1("setrecursionlimit", (recursion_limit,))
+      .expect("unable to set the python recursion limit");
+    info!("python recursion limit set to {}", recursion_limit);
+  }
+
+  if let Some(switch_interval) = config::thread_switch_interval() {
+    sys
+      .call1("setswitchinterval", (switch_interval,))
+      .expect("unable to set the python thread switch interval");
+    info!("python thread switch interval set to {}s", switch_interval);
+  }
+
+  if let Some(stack_size) = config::thread_stack_size() {
+    py.import("threading")
+      .expect("unable to import threading")
+      .call1("stack_size", (stack_size,))
+      .expect("unable to set the python thread stack size");
+    info!("python thread stack size set to {} bytes", stack_size);
+  }
+}
+
+/// The module is named after the last worker file, so a worker importing its own file gets the
+/// module being loaded from `sys.modules`, instead of executing the file a second time.
+fn get_module_name() -> (String, String) {
+  let filename = config::filenames()
+    .pop()
+    .unwrap_or_else(|| "worker.py".to_string());
+  let module_name = std::path::Path::new(&filename)
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().to_string())
+    .unwrap_or_else(|| "worker".to_string());
+  (filename, module_name)
+}
+
+/// Warns about the source importing its own module, which only sees the definitions above the import.
+fn check_self_import(contents: &str, module_name: &str) {
+  let self_import = contents.lines().map(str::trim).any(|line| {
+    line == format!("import {}", module_name)
+      || line.starts_with(&format!("import {} ", module_name))
+      || line.starts_with(&format!("from {} import", module_name))
+  });
+
+  if self_import {
+    warn!(
+      "the worker imports its own module {}, only the definitions above the import are available through it",
+      module_name
+    );
+  }
+}
+
+fn compile_module<'p>(py: Python<'p>, contents: &str) -> PyResult<&'p PyModule> {
+  let (filename, module_name) = get_module_name();
+  check_self_import(contents, &module_name);
+  PyModule::from_code(py, contents, &filename, &module_name)
+}
+
+/// Returns the module compiled from the source, reused as long as the source does not change.
+/// With `fresh`, the module is compiled again, without any state left by the previous jobs.
+pub fn load_module<'p>(py: Python<'p>, contents: &str, fresh: bool) -> PyResult<&'p PyModule> {
+  if fresh {
+    return compile_module(py, contents);
+  }
+
+  let source_hash = describe::get_source_hash(contents);
+  {
+    let cached_module = CACHED_MODULE
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+    if let Some((cached_hash, module)) = cached_module.as_ref() {
+      if *cached_hash == source_hash {
+        return Ok(py.checked_cast_as::<PyModule>(module.clone_ref(py))?);
+      }
+    }
+  }
+
+  // the module code can take a while, it runs without holding the lock
+  let module = compile_module(py, contents)?;
+  *CACHED_MODULE
+    .lock()
+    .unwrap_or_else(|error| error.into_inner()) = Some((source_hash, module.to_object(py)));
+  Ok(module)
+}
+
+/// Runs the coroutine returned by an `async def process` to completion, on a dedicated event loop.
+pub fn complete_coroutine<'p>(py: Python<'p>, response: &'p PyAny) -> PyResult<&'p PyAny> {
+  let asyncio = py.import("asyncio")?;
+  if !asyncio.call1("iscoroutine", (response,))?.is_true()? {
+    return Ok(response);
+  }
+
+  if !config::run_coroutines() {
+    response.call_method0("close")?;
+    return Err(PyErr::new::<TypeError, _>(
+      "process returned a coroutine, but coroutines are disabled (PYTHON_WORKER_RUN_COROUTINES)",
+    ));
+  }
+
+  let event_loop = asyncio.call0("new_event_loop")?;
+  let result = event_loop.call_method1("run_until_complete", (response,));
+  event_loop.call_method0("close")?;
+  result
+}
+
+/// Lets the optional `preprocess` function of the module transform the parameters given to `process`.
+pub fn preprocess<'p>(module: &'p PyModule, parameters: &'p PyDict) -> PyResult<&'p PyDict> {
+  if !module.hasattr("preprocess")? {
+    return Ok(parameters);
+  }
+
+  let response = module.call1("preprocess", (parameters,))?;
+  response.downcast_ref::<PyDict>().map_err(|_| {
+    PyErr::new::<TypeError, _>(format!(
+      "preprocess must return a dict of parameters, got {}",
+      response.get_type().name()
+    ))
+  })
+}
@@ -0,0 +1,233 @@
+use serde_json::{Map, Value};
+use std::{
+  fs::File,
+  io::Write,
+  sync::Mutex,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Failure requested by the Python worker through the callback handle.
+#[derive(Clone, Debug)]
+pub struct JobFailure {
+  pub code: String,
+  pub message: String,
+}
+
+/// Deferral requested by the Python worker, the job is requeued after the delay.
+#[derive(Clone, Debug)]
+pub struct JobDeferral {
+  pub delay: Duration,
+  pub reason: String,
+}
+
+/// Progression of a job, measured in work units whose total can be revised while processing.
+pub struct WorkProgress {
+  started: Instant,
+  total: u64,
+  done: u64,
+}
+
+impl WorkProgress {
+  fn new() -> Self {
+    WorkProgress {
+      started: Instant::now(),
+      total: 0,
+      done: 0,
+    }
+  }
+
+  pub fn set_total(&mut self, total: u64) {
+    self.total = total;
+  }
+
+  pub fn add_done(&mut self, delta: u64) {
+    self.done = self.done.saturating_add(delta);
+  }
+
+  /// `None` while the total is unknown.
+  pub fn percentage(&self) -> Option<u8> {
+    if self.total == 0 {
+      return None;
+    }
+    Some((self.done.min(self.total) * 100 / self.total) as u8)
+  }
+
+  pub fn estimated_remaining(&self) -> Option<Duration> {
+    if self.done == 0 || self.total <= self.done {
+      return None;
+    }
+    let elapsed = self.started.elapsed().as_secs_f64();
+    Some(Duration::from_secs_f64(
+      elapsed * (self.total - self.done) as f64 / self.done as f64,
+    ))
+  }
+}
+
+/// State of a job, shared between the callback handle given to Python and the worker.
+#[derive(Default)]
+pub struct JobState {
+  failure: Mutex<Option<JobFailure>>,
+  work_progress: Mutex<Option<WorkProgress>>,
+  last_progression: Mutex<Option<u8>>,
+  recorded_errors: Mutex<Vec<JobFailure>>,
+  deferral: Mutex<Option<JobDeferral>>,
+  annotations: Mutex<Map<String, Value>>,
+  log_file: Mutex<Option<File>>,
+  media_info: Mutex<Option<Value>>,
+}
+
+impl JobState {
+  pub fn set_failure(&self, failure: JobFailure) {
+    *self
+      .failure
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some(failure);
+  }
+
+  pub fn update_work_progress<F: FnOnce(&mut WorkProgress)>(&self, update: F) -> Option<u8> {
+    let mut work_progress = self
+      .work_progress
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+    let work_progress = work_progress.get_or_insert_with(WorkProgress::new);
+    update(work_progress);
+
+    if let Some(remaining) = work_progress.estimated_remaining() {
+      debug!(
+        "job progression: {}/{} work units, about {}s remaining",
+        work_progress.done,
+        work_progress.total,
+        remaining.as_secs()
+      );
+    }
+    work_progress.percentage()
+  }
+
+  /// Keeps the progression monotonic, returns `false` when the value is not above the last one.
+  pub fn advance_progression(&self, value: u8) -> bool {
+    let mut last_progression = self
+      .last_progression
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+
+    if last_progression.map(|last| value <= last).unwrap_or(false) {
+      return false;
+    }
+    *last_progression = Some(value);
+    true
+  }
+
+  /// Non-fatal error, reported with the result of the job.
+  pub fn record_error(&self, error: JobFailure) {
+    self
+      .recorded_errors
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .push(error);
+  }
+
+  pub fn take_recorded_errors(&self) -> Vec<JobFailure> {
+    std::mem::take(
+      &mut *self
+        .recorded_errors
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()),
+    )
+  }
+
+  pub fn set_deferral(&self, deferral: JobDeferral) {
+    *self
+      .deferral
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some(deferral);
+  }
+
+  pub fn take_deferral(&self) -> Option<JobDeferral> {
+    self
+      .deferral
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .take()
+  }
+
+  pub fn set_annotation(&self, key: &str, value: Value) {
+    self
+      .annotations
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .insert(key.to_string(), value);
+  }
+
+  pub fn get_annotation(&self, key: &str) -> Option<Value> {
+    self
+      .annotations
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .get(key)
+      .cloned()
+  }
+
+  pub fn take_annotations(&self) -> Map<String, Value> {
+    std::mem::take(
+      &mut *self
+        .annotations
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()),
+    )
+  }
+
+  /// The last reported media info replaces the previous one.
+  pub fn set_media_info(&self, media_info: Value) {
+    *self
+      .media_info
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some(media_info);
+  }
+
+  pub fn take_media_info(&self) -> Option<Value> {
+    self
+      .media_info
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .take()
+  }
+
+  pub fn set_log_file(&self, log_file: File) {
+    *self
+      .log_file
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some(log_file);
+  }
+
+  /// Appends a line to the log file of the job, when there is one.
+  pub fn write_log(&self, level: &str, message: &str) {
+    let written = match self
+      .log_file
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .as_mut()
+    {
+      Some(log_file) => {
+        let timestamp = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .map(|timestamp| timestamp.as_secs_f64())
+          .unwrap_or_default();
+        writeln!(log_file, "{:.3} {:<5} {}", timestamp, level, message)
+      }
+      None => Ok(()),
+    };
+
+    // logged once the file is released, as the records of the job are written to it
+    if let Err(error) = written {
+      debug!("unable to write the job log: {}", error);
+    }
+  }
+
+  pub fn take_failure(&self) -> Option<JobFailure> {
+    self
+      .failure
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .take()
+  }
+}
@@ -0,0 +1,677 @@
+use crate::{
+  helpers::{get_missing_paths, json_to_py},
+  inputs,
+  parameters::ParameterDescription,
+  validation::{ValidationError, ValidationErrorCode},
+};
+use pyo3::{
+  prelude::*,
+  types::{PyBytes, PyDict},
+};
+use serde_json::Value;
+
+/// Converts a job parameter value to Python, according to the kind declared by the worker.
+pub fn convert(
+  py: Python,
+  description: Option<&ParameterDescription>,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  if let Some(description) = description {
+    if description.strict {
+      check_strict_type(description, value)?;
+    }
+    if description.has_kind("boolean") {
+      return convert_boolean(py, description, value);
+    }
+    if description.has_kind("color") {
+      return convert_color(py, description, value);
+    }
+    if description.has_kind("enum_int") {
+      return convert_enum_int(py, description, value);
+    }
+    if description.has_kind("path") {
+      return convert_path(py, description, value);
+    }
+    if description.has_kind("paths") {
+      return convert_paths(py, description, value);
+    }
+    if description.has_kind("timerange") {
+      return convert_timerange(py, description, value);
+    }
+    if description.has_kind("typed_array") {
+      return convert_typed_array(py, description, value);
+    }
+    if description.has_kind("integer") {
+      return convert_integer(py, description, value);
+    }
+    if description.has_kind("string") {
+      if let Value::Number(number) = value {
+        return Ok(number.to_string().to_object(py));
+      }
+    }
+  }
+
+  Ok(json_to_py(py, value))
+}
+
+/// A strict parameter must be given with the transport type of its kind.
+fn check_strict_type(
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<(), ValidationError> {
+  let expected = if description.has_kind("boolean") {
+    ("a boolean", value.is_boolean())
+  } else if description.has_kind("integer") {
+    ("an integer", value.is_i64())
+  } else if description.has_kind("string") {
+    ("a string", value.is_string())
+  } else {
+    return Ok(());
+  };
+
+  match expected {
+    (_, true) => Ok(()),
+    (type_name, false) => Err(ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      format!("expected {}, got {}", type_name, value),
+    )),
+  }
+}
+
+/// Accepts the integers given as strings.
+fn convert_integer(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  match value {
+    Value::String(value) => value
+      .trim()
+      .parse::<i64>()
+      .map(|value| value.to_object(py))
+      .map_err(|_| {
+        ValidationError::new(
+          ValidationErrorCode::InvalidValue,
+          &description.identifier,
+          format!("expected an integer, got {:?}", value),
+        )
+      }),
+    value => Ok(json_to_py(py, value)),
+  }
+}
+
+/// Accepts the usual string and integer representations of booleans, sent by loosely-typed producers.
+fn convert_boolean(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let converted = match value {
+    Value::Bool(value) => Some(*value),
+    Value::Number(number) => match number.as_i64() {
+      Some(1) => Some(true),
+      Some(0) => Some(false),
+      _ => None,
+    },
+    Value::String(value) => match value.trim().to_lowercase().as_str() {
+      "true" | "1" | "yes" | "on" => Some(true),
+      "false" | "0" | "no" | "off" => Some(false),
+      _ => None,
+    },
+    _ => None,
+  };
+
+  converted
+    .map(|converted| converted.to_object(py))
+    .ok_or_else(|| {
+      ValidationError::new(
+        ValidationErrorCode::InvalidValue,
+        &description.identifier,
+        format!("ambiguous boolean value {}", value),
+      )
+    })
+}
+
+/// Parses `#RRGGBB`, `#RRGGBBAA`, `rgb(r, g, b)` and `rgba(r, g, b, a)` (alpha between 0 and 1).
+fn parse_color(color: &str) -> Option<[u8; 4]> {
+  let color = color.trim().to_lowercase();
+
+  if let Some(hexadecimal) = color.strip_prefix('#') {
+    if !(hexadecimal.len() == 6 || hexadecimal.len() == 8) || !hexadecimal.is_ascii() {
+      return None;
+    }
+    let component = |index: usize| u8::from_str_radix(&hexadecimal[index..index + 2], 16).ok();
+    let alpha = if hexadecimal.len() == 8 {
+      component(6)?
+    } else {
+      255
+    };
+    return Some([component(0)?, component(2)?, component(4)?, alpha]);
+  }
+
+  let (arguments, with_alpha) = if let Some(arguments) = color.strip_prefix("rgba(") {
+    (arguments, true)
+  } else {
+    (color.strip_prefix("rgb(")?, false)
+  };
+  let arguments: Vec<&str> = arguments
+    .strip_suffix(')')?
+    .split(',')
+    .map(str::trim)
+    .collect();
+
+  if arguments.len() != if with_alpha { 4 } else { 3 } {
+    return None;
+  }
+  let component = |index: usize| arguments[index].parse::<u8>().ok();
+  let alpha = if with_alpha {
+    let alpha = arguments[3].parse::<f64>().ok()?;
+    if !(0.0..=1.0).contains(&alpha) {
+      return None;
+    }
+    (alpha * 255.0).round() as u8
+  } else {
+    255
+  };
+  Some([component(0)?, component(1)?, component(2)?, alpha])
+}
+
+/// Passes the color as a `{r, g, b, a}` dict of integers between 0 and 255.
+fn convert_color(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let invalid_value = || {
+    ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      format!(
+        "invalid color {}, expected #RRGGBB, #RRGGBBAA, rgb(r, g, b) or rgba(r, g, b, a)",
+        value
+      ),
+    )
+  };
+
+  let [r, g, b, a] = value
+    .as_str()
+    .and_then(parse_color)
+    .ok_or_else(invalid_value)?;
+
+  let dict = PyDict::new(py);
+  for (key, component) in [("r", r), ("g", g), ("b", b), ("a", a)].iter() {
+    dict.set_item(key, component).map_err(|_| invalid_value())?;
+  }
+  Ok(dict.to_object(py))
+}
+
+/// Accepts the name or the code of the value.
+fn convert_enum_int(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let found = description
+    .enum_values
+    .iter()
+    .find(|(name, code)| match value {
+      Value::Number(number) => number.as_i64() == Some(*code),
+      Value::String(value) => value == name || value.parse::<i64>().ok() == Some(*code),
+      _ => false,
+    });
+
+  match found {
+    Some((name, _)) if description.pass_name => Ok(name.to_object(py)),
+    Some((_, code)) => Ok(code.to_object(py)),
+    None => Err(ValidationError::new(
+      ValidationErrorCode::EnumMismatch,
+      &description.identifier,
+      format!(
+        "invalid value {}, expected one of: {}",
+        value,
+        description
+          .enum_values
+          .iter()
+          .map(|(name, code)| format!("{} ({})", name, code))
+          .collect::<Vec<String>>()
+          .join(", ")
+      ),
+    )),
+  }
+}
+
+/// Python `array` type code of each supported item type.
+fn get_array_typecode(dtype: &str) -> Option<&'static str> {
+  match dtype {
+    "i8" => Some("b"),
+    "u8" => Some("B"),
+    "i16" => Some("h"),
+    "u16" => Some("H"),
+    "i32" => Some("i"),
+    "u32" => Some("I"),
+    "i64" => Some("q"),
+    "u64" => Some("Q"),
+    "f32" => Some("f"),
+    "f64" => Some("d"),
+    _ => None,
+  }
+}
+
+/// Wraps the base64 encoded bytes in an `array.array`, without converting each item to a Python object.
+fn convert_typed_array(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let dtype = description.dtype.as_deref().unwrap_or("f64");
+  let invalid_value = |message: String| {
+    ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      message,
+    )
+  };
+
+  let typecode = get_array_typecode(dtype)
+    .ok_or_else(|| invalid_value(format!("unsupported dtype {}", dtype)))?;
+
+  let bytes = value
+    .as_str()
+    .and_then(|encoded| base64::decode(encoded).ok())
+    .ok_or_else(|| invalid_value("expected base64 encoded bytes".to_string()))?;
+
+  let array = py
+    .import("array")
+    .and_then(|array| array.call1("array", (typecode,)))
+    .map_err(|error| invalid_value(format!("{:?}", error)))?;
+  array
+    .call_method1("frombytes", (PyBytes::new(py, &bytes),))
+    .map_err(|_| {
+      invalid_value(format!(
+        "byte length is not a multiple of the {} size",
+        dtype
+      ))
+    })?;
+  Ok(array.to_object(py))
+}
+
+/// Fetches the inputs given as URIs, and checks that each path exists.
+/// All the failures are reported in one error.
+fn resolve_paths(
+  description: &ParameterDescription,
+  uris: &[String],
+) -> Result<Vec<String>, ValidationError> {
+  let mut paths = vec![];
+  let mut failures = vec![];
+  for uri in uris {
+    match inputs::resolve_input(uri) {
+      Ok(path) => paths.push(path),
+      Err(message) => failures.push(message),
+    }
+  }
+
+  let missing_paths = get_missing_paths(&paths);
+  if !missing_paths.is_empty() {
+    failures.push(format!("missing paths: {}", missing_paths.join(", ")));
+  }
+
+  if !failures.is_empty() {
+    return Err(ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      failures.join(", "),
+    ));
+  }
+  Ok(paths)
+}
+
+fn convert_path(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let uri = value.as_str().ok_or_else(|| {
+    ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      format!("expected a path, got {}", value),
+    )
+  })?;
+
+  let paths = resolve_paths(description, &[uri.to_string()])?;
+  Ok(paths[0].to_object(py))
+}
+
+fn convert_paths(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let uris: Vec<String> = serde_json::from_value(value.clone()).map_err(|_| {
+    ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      format!("expected a list of paths, got {}", value),
+    )
+  })?;
+
+  Ok(resolve_paths(description, &uris)?.to_object(py))
+}
+
+/// Parses a time code (`HH:MM:SS`, `MM:SS` or `SS`, with optional decimals) or a number of seconds.
+fn parse_time(value: &Value) -> Option<f64> {
+  if let Some(seconds) = value.as_f64() {
+    return Some(seconds).filter(|seconds| seconds.is_finite() && *seconds >= 0.0);
+  }
+
+  let parts = value
+    .as_str()?
+    .split(':')
+    .map(|part| part.trim().parse::<f64>().ok())
+    .collect::<Option<Vec<f64>>>()?;
+
+  if parts.is_empty()
+    || parts.len() > 3
+    || parts.iter().any(|part| !part.is_finite() || *part < 0.0)
+  {
+    return None;
+  }
+
+  Some(parts.iter().fold(0.0, |total, part| total * 60.0 + part))
+}
+
+/// Passes the `in` and `out` points as a dict of seconds.
+fn convert_timerange(
+  py: Python,
+  description: &ParameterDescription,
+  value: &Value,
+) -> Result<PyObject, ValidationError> {
+  let invalid_value = |message: String| {
+    ValidationError::new(
+      ValidationErrorCode::InvalidValue,
+      &description.identifier,
+      message,
+    )
+  };
+
+  // the transport type is a string, which contains the JSON object
+  let range = match value {
+    Value::String(contents) => serde_json::from_str(contents).map_err(|_| {
+      invalid_value(format!(
+        "expected a {{\"in\", \"out\"}} object, got {}",
+        contents
+      ))
+    })?,
+    value => value.clone(),
+  };
+
+  let get_point = |key: &str| {
+    range
+      .get(key)
+      .and_then(parse_time)
+      .ok_or_else(|| invalid_value(format!("missing or invalid \"{}\" point in {}", key, range)))
+  };
+  let in_point = get_point("in")?;
+  let out_point = get_point("out")?;
+
+  if in_point >= out_point {
+    return Err(invalid_value(format!(
+      "the in point ({}s) must be before the out point ({}s)",
+      in_point, out_point
+    )));
+  }
+
+  let dict = PyDict::new(py);
+  dict
+    .set_item("in", in_point)
+    .and_then(|()| dict.set_item("out", out_point))
+    .map_err(|error| invalid_value(format!("{:?}", error)))?;
+  Ok(dict.to_object(py))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::{collections::HashMap, sync::Arc};
+
+  fn boolean_description() -> ParameterDescription {
+    ParameterDescription {
+      identifier: "enabled".to_string(),
+      label: "Enabled".to_string(),
+      kind: vec!["boolean".to_string()],
+      required: false,
+      unit: None,
+      enum_values: vec![],
+      pass_name: false,
+      dtype: None,
+      oneof: None,
+      strict: false,
+      sensitive_fields: None,
+      default: None,
+      requires: vec![],
+      conflicts: vec![],
+    }
+  }
+
+  fn convert_to_bool(value: Value) -> Result<bool, ValidationError> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let description = boolean_description();
+
+    convert(py, Some(&description), &value).map(|converted| converted.extract::<bool>(py).unwrap())
+  }
+
+  fn description(kind: &str) -> ParameterDescription {
+    ParameterDescription {
+      identifier: "parameter".to_string(),
+      label: "Parameter".to_string(),
+      kind: vec![kind.to_string()],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn boolean_accepts_strings_and_integers() {
+    assert!(convert_to_bool(json!("true")).unwrap());
+    assert!(!convert_to_bool(json!("false")).unwrap());
+    assert!(convert_to_bool(json!("1")).unwrap());
+    assert!(!convert_to_bool(json!("0")).unwrap());
+    assert!(convert_to_bool(json!(true)).unwrap());
+    assert!(convert_to_bool(json!(1)).unwrap());
+  }
+
+  #[test]
+  fn boolean_rejects_ambiguous_values() {
+    assert!(convert_to_bool(json!("maybe")).is_err());
+    assert!(convert_to_bool(json!(2)).is_err());
+  }
+
+  #[test]
+  fn color_is_converted_to_rgba_components() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let description = description("color");
+    let to_rgba = |value: Value| {
+      convert(py, Some(&description), &value)
+        .map(|converted| converted.extract::<HashMap<String, u8>>(py).unwrap())
+    };
+
+    let rgba = to_rgba(json!("#FF000080")).unwrap();
+    assert_eq!(
+      (rgba["r"], rgba["g"], rgba["b"], rgba["a"]),
+      (255, 0, 0, 128)
+    );
+    let rgba = to_rgba(json!("rgba(1, 2, 3, 0.5)")).unwrap();
+    assert_eq!((rgba["r"], rgba["g"], rgba["b"], rgba["a"]), (1, 2, 3, 128));
+
+    for value in &[
+      json!("#12345"),
+      json!("blue"),
+      json!("rgb(256, 0, 0)"),
+      json!(42),
+    ] {
+      let error = to_rgba(value.clone()).unwrap_err();
+      assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+    }
+  }
+
+  #[test]
+  fn enum_int_accepts_the_name_or_the_code() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let mut description = description("enum_int");
+    description.enum_values = vec![("low".to_string(), 1), ("high".to_string(), 2)];
+
+    for value in &[json!("high"), json!(2), json!("2")] {
+      let converted = convert(py, Some(&description), value).unwrap();
+      assert_eq!(converted.extract::<i64>(py).unwrap(), 2);
+    }
+
+    let error = convert(py, Some(&description), &json!("medium")).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::EnumMismatch);
+    let error = convert(py, Some(&description), &json!(3)).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::EnumMismatch);
+
+    description.pass_name = true;
+    let converted = convert(py, Some(&description), &json!(1)).unwrap();
+    assert_eq!(converted.extract::<String>(py).unwrap(), "low");
+  }
+
+  #[test]
+  fn typed_array_wraps_the_decoded_bytes() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let mut description = description("typed_array");
+    description.dtype = Some("f32".to_string());
+
+    // 1.0 and 2.0 as little-endian f32
+    let converted = convert(py, Some(&description), &json!("AACAPwAAAEA=")).unwrap();
+    let items: Vec<f64> = converted
+      .call_method0(py, "tolist")
+      .unwrap()
+      .extract(py)
+      .unwrap();
+    assert_eq!(items, vec![1.0, 2.0]);
+
+    // not base64, and 3 bytes for a 4 bytes item type
+    for value in &[json!("not base64!"), json!("AAAA")] {
+      let error = convert(py, Some(&description), value).unwrap_err();
+      assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+    }
+
+    description.dtype = Some("f16".to_string());
+    let error = convert(py, Some(&description), &json!("AACAPwAAAEA=")).unwrap_err();
+    assert_eq!(error.message, "unsupported dtype f16");
+  }
+
+  #[test]
+  fn timerange_is_converted_to_seconds() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let description = description("timerange");
+    let to_range = |value: Value| {
+      convert(py, Some(&description), &value)
+        .map(|converted| converted.extract::<HashMap<String, f64>>(py).unwrap())
+    };
+
+    let range = to_range(json!({"in": "00:01:01.5", "out": 90})).unwrap();
+    assert_eq!((range["in"], range["out"]), (61.5, 90.0));
+    let range = to_range(json!(r#"{"in": "1:00", "out": "1:30"}"#)).unwrap();
+    assert_eq!((range["in"], range["out"]), (60.0, 90.0));
+
+    for value in &[
+      json!({"in": "00:00:02", "out": "00:00:01"}),
+      json!({"in": 1}),
+      json!({"in": "one", "out": 2}),
+      json!("not a range"),
+    ] {
+      let error = to_range(value.clone()).unwrap_err();
+      assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+    }
+  }
+
+  #[test]
+  fn path_must_exist() {
+    inputs::register_resolver(Arc::new(inputs::FileResolver {}));
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let description = description("path");
+    let existing = std::env::temp_dir().join(format!("py_amqp_worker_path_{}", std::process::id()));
+    std::fs::write(&existing, "input").unwrap();
+
+    let converted = convert(
+      py,
+      Some(&description),
+      &json!(format!("file://{}", existing.display())),
+    )
+    .unwrap();
+    assert_eq!(
+      converted.extract::<String>(py).unwrap(),
+      existing.to_string_lossy()
+    );
+
+    let missing = existing.with_extension("missing");
+    let error = convert(py, Some(&description), &json!(missing.to_string_lossy())).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+    assert!(error.message.contains("missing paths"));
+    let error = convert(py, Some(&description), &json!(["a", "list"])).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+
+    std::fs::remove_file(&existing).unwrap();
+  }
+
+  #[test]
+  fn paths_report_all_the_missing_paths() {
+    inputs::register_resolver(Arc::new(inputs::FileResolver {}));
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let description = description("paths");
+    let existing =
+      std::env::temp_dir().join(format!("py_amqp_worker_paths_{}", std::process::id()));
+    std::fs::write(&existing, "input").unwrap();
+    let existing = existing.to_string_lossy().to_string();
+
+    let converted = convert(py, Some(&description), &json!([existing, existing])).unwrap();
+    assert_eq!(
+      converted.extract::<Vec<String>>(py).unwrap(),
+      vec![existing.clone(), existing.clone()]
+    );
+
+    let first_missing = format!("{}.first", existing);
+    let second_missing = format!("{}.second", existing);
+    let error = convert(
+      py,
+      Some(&description),
+      &json!([first_missing, existing, second_missing]),
+    )
+    .unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+    assert!(error.message.contains(&first_missing));
+    assert!(error.message.contains(&second_missing));
+    let error = convert(py, Some(&description), &json!(existing)).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+
+    std::fs::remove_file(&existing).unwrap();
+  }
+
+  #[test]
+  fn integer_is_coerced_unless_strict() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let mut description = description("integer");
+
+    for value in &[json!(" 42 "), json!(42)] {
+      let converted = convert(py, Some(&description), value).unwrap();
+      assert_eq!(converted.extract::<i64>(py).unwrap(), 42);
+    }
+    let error = convert(py, Some(&description), &json!("4.2")).unwrap_err();
+    assert_eq!(error.code, ValidationErrorCode::InvalidValue);
+
+    description.strict = true;
+    assert!(convert(py, Some(&description), &json!(42)).is_ok());
+    let error = convert(py, Some(&description), &json!("42")).unwrap_err();
+    assert_eq!(error.message, "expected an integer, got \"42\"");
+  }
+}
@@ -0,0 +1,108 @@
+use crate::config;
+use log::Level;
+use std::{sync::Mutex, time::Instant};
+
+pub const LOG_TARGET: &str = "python";
+
+lazy_static! {
+  static ref LOG_RATE_LIMITER: Mutex<Option<TokenBucket>> =
+    Mutex::new(config::log_rate().map(TokenBucket::new));
+}
+
+/// Allows `rate` lines per second, with bursts up to one second of lines.
+struct TokenBucket {
+  rate: f64,
+  tokens: f64,
+  last_refill: Instant,
+  dropped: u64,
+}
+
+impl TokenBucket {
+  fn new(rate: f64) -> Self {
+    TokenBucket {
+      rate,
+      tokens: rate,
+      last_refill: Instant::now(),
+      dropped: 0,
+    }
+  }
+
+  fn try_acquire(&mut self) -> bool {
+    let elapsed = self.last_refill.elapsed();
+    let elapsed_seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+    self.tokens = (self.tokens + elapsed_seconds * self.rate).min(self.rate.max(1.0));
+    self.last_refill = Instant::now();
+
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      self.dropped += 1;
+      false
+    }
+  }
+
+  /// Number of lines dropped since the last summary, if any.
+  fn take_dropped(&mut self) -> Option<u64> {
+    Some(std::mem::take(&mut self.dropped)).filter(|dropped| *dropped > 0)
+  }
+}
+
+pub fn parse_level(level: &str) -> Level {
+  match level.to_lowercase().as_str() {
+    "critical" | "error" => Level::Error,
+    "warning" | "warn" => Level::Warn,
+    "debug" => Level::Debug,
+    "trace" => Level::Trace,
+    _ => Level::Info,
+  }
+}
+
+/// Forwards a line logged by the Python worker, dropping the excess lines when rate limited.
+pub fn forward(level: Level, message: &str) {
+  let mut rate_limiter = LOG_RATE_LIMITER
+    .lock()
+    .unwrap_or_else(|error| error.into_inner());
+
+  if let Some(token_bucket) = rate_limiter.as_mut() {
+    if !token_bucket.try_acquire() {
+      return;
+    }
+
+    if let Some(dropped) = token_bucket.take_dropped() {
+      warn!(target: LOG_TARGET, "{} log lines dropped", dropped);
+    }
+  }
+
+  log!(target: LOG_TARGET, level, "{}", message);
+}
+
+/// Reports the lines dropped at the end of a job, when no later line got through to report them.
+pub fn report_dropped_lines() {
+  let dropped = LOG_RATE_LIMITER
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .as_mut()
+    .and_then(TokenBucket::take_dropped);
+
+  if let Some(dropped) = dropped {
+    warn!(target: LOG_TARGET, "{} log lines dropped", dropped);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dropped_lines_are_reported_once() {
+    let mut token_bucket = TokenBucket::new(1.0);
+    assert!(token_bucket.try_acquire());
+    assert!(!token_bucket.try_acquire());
+    assert!(!token_bucket.try_acquire());
+
+    // the worker went quiet, the summary is taken at the end of the job
+    assert_eq!(token_bucket.take_dropped(), Some(2));
+    assert_eq!(token_bucket.take_dropped(), None);
+  }
+}
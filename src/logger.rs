@@ -0,0 +1,141 @@
+use crate::{config, job_state::JobState, log_forwarding};
+use chrono::Utc;
+use env_logger::{Builder, Logger};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{cell::RefCell, fs, io::Write, sync::Arc};
+
+thread_local! {
+  static JOB_STATE: RefCell<Option<Arc<JobState>>> = const { RefCell::new(None) };
+}
+
+/// Writes the records logged while a job is processed to its log file, besides the worker logs.
+struct JobLogger {
+  inner: Logger,
+}
+
+impl Log for JobLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    self.inner.enabled(metadata) || metadata.level() <= LevelFilter::Info
+  }
+
+  fn log(&self, record: &Record) {
+    self.inner.log(record);
+
+    // the lines logged from Python are already written by the callback handle
+    if record.level() > LevelFilter::Info || record.target() == log_forwarding::LOG_TARGET {
+      return;
+    }
+    let _ = JOB_STATE.try_with(|job_state| {
+      if let Ok(job_state) = job_state.try_borrow() {
+        if let Some(job_state) = job_state.as_ref() {
+          job_state.write_log(&record.level().to_string(), &record.args().to_string());
+        }
+      }
+    });
+  }
+
+  fn flush(&self) {
+    self.inner.flush();
+  }
+}
+
+/// Stops writing the records of the thread to the job log when dropped.
+pub struct JobLogGuard;
+
+impl Drop for JobLogGuard {
+  fn drop(&mut self) {
+    let _ = JOB_STATE.try_with(|job_state| job_state.replace(None));
+  }
+}
+
+/// The records logged by the current thread are also written to the log file of the job.
+pub fn capture_job_log(job_state: Arc<JobState>) -> JobLogGuard {
+  JOB_STATE.with(|current| current.replace(Some(job_state)));
+  JobLogGuard
+}
+
+fn get_container_id(cgroup: &str) -> String {
+  cgroup
+    .lines()
+    .next()
+    .and_then(|line| line.splitn(3, ':').nth(2))
+    .and_then(|path| path.split("/docker/").nth(1))
+    .map(|identifier| identifier.chars().take(12).collect())
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Same format as the AMQP worker: date, container, queue, job identifier (the log target) and level.
+pub fn init() {
+  let container_id = fs::read_to_string("/proc/self/cgroup")
+    .map(|cgroup| get_container_id(&cgroup))
+    .unwrap_or_else(|_| "unknown".to_string());
+  let queue = config::queue_name();
+
+  let inner = Builder::from_default_env()
+    .format(move |stream, record| {
+      writeln!(
+        stream,
+        "{} - {} - {} - {} - {} - {}",
+        Utc::now(),
+        container_id,
+        queue,
+        record.target().parse::<i64>().unwrap_or(-1),
+        record.level(),
+        record.args(),
+      )
+    })
+    .build();
+
+  // the job logs get the info records, whatever the level of the worker logs
+  let max_level = inner.filter().max(LevelFilter::Info);
+  log::set_boxed_logger(Box::new(JobLogger { inner }))
+    .map(|()| log::set_max_level(max_level))
+    .expect("unable to initialize the logger");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use log::Level;
+
+  fn log_record(logger: &JobLogger, target: &str, message: &str) {
+    logger.log(
+      &Record::builder()
+        .level(Level::Info)
+        .target(target)
+        .args(format_args!("{}", message))
+        .build(),
+    );
+  }
+
+  #[test]
+  fn records_are_written_to_the_job_log_while_captured() {
+    let path = std::env::temp_dir().join(format!("py_amqp_worker_job_{}.log", std::process::id()));
+    let job_state = Arc::new(JobState::default());
+    job_state.set_log_file(fs::File::create(&path).unwrap());
+    let logger = JobLogger {
+      inner: Builder::new().build(),
+    };
+
+    log_record(&logger, "271", "before the job");
+    {
+      let _guard = capture_job_log(job_state.clone());
+      log_record(&logger, "271", "uploading the outputs");
+      log_record(
+        &logger,
+        log_forwarding::LOG_TARGET,
+        "written by the callback handle",
+      );
+    }
+    log_record(&logger, "271", "after the job");
+
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(contents.lines().count(), 1, "{}", contents);
+    assert!(
+      contents.contains("INFO  uploading the outputs"),
+      "{}",
+      contents
+    );
+  }
+}
@@ -1,66 +1,256 @@
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 
-use crate::helpers::get_destination_paths;
-use amqp_worker::{
-  job::*,
-  publish_job_progression, start_worker,
-  worker::{Parameter, ParameterType},
-  MessageError, MessageEvent,
-  Parameter::*,
+use crate::{
+  callback_handle::CallbackHandle,
+  consumer::DeliveryInfo,
+  helpers::{
+    get_destinations, get_missing_paths, get_parameter_value, get_string_list, map_job_result,
+    py_to_json, with_json_parameter, with_string_parameter,
+  },
+  job_state::JobState,
+  messages::{ChannelPublisher, MemoryPublisher, MessagePublisher},
+  parameters::{find_description, ParameterDescription},
+  secret::SecretValues,
+  semaphore::KeyedSemaphore,
+  validation::{ValidationError, ValidationErrorCode},
 };
+use amqp_worker::{job::*, worker::Parameter, MessageError, MessageEvent, Parameter::*};
 use lapin_futures::Channel;
-use pyo3::{prelude::*, types::*};
+use pyo3::{
+  exceptions::{KeyboardInterrupt, SystemExit},
+  prelude::*,
+  types::*,
+};
 use semver::Version;
-use std::{env, fs};
+use std::{
+  env, fs,
+  sync::{Arc, Mutex},
+  time::Instant,
+};
 
+mod batch;
+mod cache;
+// the code generated by #[pyclass] computes a manual div_ceil
+#[allow(clippy::manual_div_ceil)]
+mod callback_handle;
+mod cleanup;
+mod config;
+mod consumer;
+mod describe;
+mod duration;
+mod failure;
+mod fixtures;
+mod health;
 mod helpers;
+mod inputs;
+mod interpreter;
+mod job_state;
+mod kinds;
+mod log_forwarding;
+mod logger;
+mod media_info;
+mod messages;
+mod output_profile;
+mod outputs;
+mod parameters;
+mod process_stats;
+mod registration;
+mod result_cache;
+mod secret;
+mod semaphore;
+mod signatures;
+// the code generated by #[pyclass] computes a manual div_ceil
+#[allow(clippy::manual_div_ceil)]
+mod span_handle;
+mod telemetry;
+mod upload;
+mod validation;
+mod webhook;
+
+lazy_static! {
+  static ref SERIAL_PROCESS_LOCK: Mutex<()> = Mutex::new(());
+  static ref RESOURCE_SEMAPHORE: KeyedSemaphore = KeyedSemaphore::default();
+  static ref PARAMETERS_DESCRIPTION: Mutex<Option<(String, Vec<ParameterDescription>)>> =
+    Mutex::new(None);
+}
 
 #[derive(Debug)]
 struct PythonWorkerEvent {}
 
+fn get_profile_filename(filename: &str) -> String {
+  let profile = match config::profile() {
+    Some(profile) => profile,
+    None => return filename.to_string(),
+  };
+
+  let path = std::path::Path::new(filename);
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let profile_filename = match path.extension() {
+    Some(extension) => format!("{}.{}.{}", stem, profile, extension.to_string_lossy()),
+    None => format!("{}.{}", stem, profile),
+  };
+  let profile_path = path.with_file_name(profile_filename);
+
+  if profile_path.exists() {
+    profile_path.to_string_lossy().to_string()
+  } else {
+    filename.to_string()
+  }
+}
+
 impl PythonWorkerEvent {
   fn read_python_file(&self) -> String {
-    let filename = env::var("PYTHON_WORKER_FILENAME").unwrap_or_else(|_| "worker.py".to_string());
-
-    fs::read_to_string(&filename)
-      .unwrap_or_else(|_| panic!("unable to open and read file: {}", filename))
+    config::filenames()
+      .iter()
+      .map(|filename| get_profile_filename(filename))
+      .map(|filename| {
+        fs::read_to_string(&filename)
+          .unwrap_or_else(|_| panic!("unable to open and read file: {}", filename))
+      })
+      .collect::<Vec<String>>()
+      .join("\n")
   }
 
   fn get_string_from_module(&self, method: &str) -> String {
+    self
+      .get_optional_string_from_module(method)
+      .unwrap_or_else(|| panic!("unable to call {} in your module", method))
+  }
+
+  /// Returns `None` only when the function is not defined in the module.
+  fn call_optional_module_function<T, F>(&self, function: &str, convert: F) -> Option<T>
+  where
+    F: FnOnce(&PyAny) -> T,
+  {
     let contents = self.read_python_file();
 
     let gil = Python::acquire_gil();
     let py = gil.python();
-    let python_module = PyModule::from_code(py, &contents, "worker.py", "worker")
-      .expect("unable to create the python module");
+    let python_module =
+      interpreter::load_module(py, &contents, false).expect("unable to create the python module");
+
+    if !python_module.hasattr(function).unwrap_or(false) {
+      return None;
+    }
 
-    let response: String = python_module
-      .call0(method)
-      .unwrap_or_else(|_| panic!("unable to call {} in your module", method))
-      .extract()
-      .unwrap_or_else(|_| panic!("unable to found a return value for {} function", method));
+    let response = python_module
+      .call0(function)
+      .unwrap_or_else(|_| panic!("unable to call {} in your module", function));
+    Some(convert(response))
+  }
+
+  fn get_optional_string_from_module(&self, method: &str) -> Option<String> {
+    self.call_optional_module_function(method, |response| {
+      response
+        .extract()
+        .unwrap_or_else(|_| panic!("unable to found a return value for {} function", method))
+    })
+  }
+
+  fn get_parameters_description(&self) -> Vec<ParameterDescription> {
+    let contents = self.read_python_file();
+    let source_hash = describe::get_source_hash(&contents);
+
+    if let Some((cached_hash, description)) = PARAMETERS_DESCRIPTION
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .as_ref()
+    {
+      if *cached_hash == source_hash {
+        return description.clone();
+      }
+    }
+
+    // the lock is not held while reading, as it requires the GIL
+    let description = self.read_parameters_description(&contents);
+    *PARAMETERS_DESCRIPTION
+      .lock()
+      .unwrap_or_else(|error| error.into_inner()) = Some((source_hash, description.clone()));
+    description
+  }
+
+  fn read_parameters_description(&self, contents: &str) -> Vec<ParameterDescription> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let python_module =
+      interpreter::load_module(py, contents, false).expect("unable to create the python module");
+
+    let response = python_module
+      .call0("get_parameters")
+      .unwrap_or_else(|_| panic!("unable to call get_parameters in your module"))
+      .downcast_ref::<PyList>()
+      .unwrap();
 
     response
+      .iter()
+      .map(|item| {
+        let object = item.downcast_ref::<PyDict>().expect("not a python dict");
+        ParameterDescription::from_python(py, object)
+      })
+      .collect()
   }
-}
 
-#[pyclass]
-struct CallbackHandle {
-  channel: Channel,
-  job: Job,
-}
+  fn get_capabilities(&self) -> Option<Vec<String>> {
+    self.call_optional_module_function("get_capabilities", |response| {
+      response
+        .extract()
+        .expect("unable to found a list of strings for get_capabilities function")
+    })
+  }
 
-#[pymethods]
-impl CallbackHandle {
-  fn publish_job_progression(&self, value: u8) -> bool {
-    publish_job_progression(Some(&self.channel), &self.job, value).is_ok()
+  fn get_output_description(&self) -> Option<Vec<outputs::OutputDescription>> {
+    self.call_optional_module_function("get_output_parameters", |response| {
+      response
+        .downcast_ref::<PyList>()
+        .expect("get_output_parameters must return a list")
+        .iter()
+        .map(|item| {
+          let object = item.downcast_ref::<PyDict>().expect("not a python dict");
+          outputs::OutputDescription::from_python(object)
+        })
+        .collect()
+    })
+  }
+
+  fn has_capability(&self, capability: &str) -> bool {
+    self
+      .get_capabilities()
+      .map(|capabilities| capabilities.iter().any(|declared| declared == capability))
+      .unwrap_or(true)
+  }
+
+  fn is_reentrant(&self) -> bool {
+    self
+      .call_optional_module_function("is_reentrant", |response| {
+        response
+          .is_true()
+          .expect("unable to found a boolean return value for is_reentrant function")
+      })
+      .unwrap_or(false)
   }
 }
 
 impl MessageEvent for PythonWorkerEvent {
   fn get_name(&self) -> String {
-    self.get_string_from_module("get_name")
+    self
+      .get_optional_string_from_module("get_name")
+      .unwrap_or_else(|| {
+        let name = env::var("PYTHON_WORKER_NAME").unwrap_or_else(|_| {
+          env::current_exe()
+            .ok()
+            .and_then(|path| {
+              path
+                .file_stem()
+                .map(|name| name.to_string_lossy().to_string())
+            })
+            .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+        });
+        info!("get_name is not defined in your module, use {}", name);
+        name
+      })
   }
 
   fn get_short_description(&self) -> String {
@@ -72,167 +262,591 @@ impl MessageEvent for PythonWorkerEvent {
   }
 
   fn get_version(&self) -> Version {
-    Version::parse(&self.get_string_from_module("get_version"))
-      .expect("unable to parse version (please use SemVer format)")
+    let version = self
+      .get_optional_string_from_module("get_version")
+      .unwrap_or_else(|| {
+        let version = env::var("PYTHON_WORKER_VERSION")
+          .unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
+        info!("get_version is not defined in your module, use {}", version);
+        version
+      });
+
+    Version::parse(&version).expect("unable to parse version (please use SemVer format)")
   }
 
   fn get_parameters(&self) -> Vec<Parameter> {
-    let contents = self.read_python_file();
-
-    let gil = Python::acquire_gil();
-    let py = gil.python();
-    let python_module = PyModule::from_code(py, &contents, "worker.py", "worker")
-      .expect("unable to create the python module");
-
-    let response = python_module
-      .call0("get_parameters")
-      .unwrap_or_else(|_| panic!("unable to call get_parameters in your module".to_string()))
-      .downcast_ref::<PyList>()
-      .unwrap();
-
-    let mut parameters = vec![];
+    self
+      .get_parameters_description()
+      .iter()
+      .map(ParameterDescription::to_worker_parameter)
+      .collect()
+  }
 
-    for item in response.iter() {
-      let object = item.downcast_ref::<PyDict>().expect("not a python dict");
+  fn process(
+    &self,
+    channel: Option<&Channel>,
+    job: &Job,
+    job_result: JobResult,
+  ) -> Result<JobResult, MessageError> {
+    let memory_publisher = Arc::new(MemoryPublisher::default());
+    let publisher: Arc<dyn MessagePublisher> = match channel {
+      Some(channel) => Arc::new(ChannelPublisher::new(channel.clone())),
+      None => memory_publisher.clone(),
+    };
+    let result = self.process_job(publisher, job, job_result, &DeliveryInfo::default());
 
-      let label = object
-        .get_item("label")
-        .expect("missing label in parameter")
-        .to_string();
-      let identifier = object
-        .get_item("identifier")
-        .expect("missing identifier in parameter")
-        .to_string();
+    for published in memory_publisher.messages() {
+      debug!(
+        "no channel, skip {}: {}",
+        published.routing_key,
+        published.message()
+      );
+    }
+    result
+  }
+}
 
-      let kind_list = object
-        .get_item("kind")
-        .expect("missing kind in parameter")
-        .downcast_ref::<PyList>()
-        .unwrap();
-
-      let mut parameter_types = vec![];
-
-      for kind in kind_list.iter() {
-        let value = kind
-          .downcast_ref::<PyString>()
-          .expect("not a python string")
-          .to_string()
-          .unwrap();
-        let parameter_type: ParameterType = serde_json::from_str(&format!("{:?}", value)).unwrap();
-        parameter_types.push(parameter_type);
-      }
+impl PythonWorkerEvent {
+  fn process_job(
+    &self,
+    publisher: Arc<dyn MessagePublisher>,
+    job: &Job,
+    job_result: JobResult,
+    delivery: &DeliveryInfo,
+  ) -> Result<JobResult, MessageError> {
+    let mut secret_values = SecretValues::default();
+    let mut fixture_parameters = None;
+    let result = self.run_job(
+      publisher,
+      job,
+      job_result,
+      delivery,
+      &mut secret_values,
+      &mut fixture_parameters,
+    );
+    log_forwarding::report_dropped_lines();
 
-      let required = object
-        .get_item("required")
-        .unwrap_or_else(|| PyBool::new(py, false).as_ref())
-        .is_true()
-        .unwrap();
-
-      parameters.push(Parameter {
-        label,
-        identifier,
-        kind: parameter_types,
-        required,
-      });
+    // written whatever the exit path, the failed jobs are the most useful to replay
+    if let Some(fixture_directory) = config::fixture_directory() {
+      fixtures::write_fixture(
+        &fixture_directory,
+        job,
+        fixture_parameters,
+        &result,
+        &secret_values,
+      );
     }
-
-    parameters
+    result
   }
 
-  fn process(
+  fn run_job(
     &self,
-    channel: Option<&Channel>,
+    publisher: Arc<dyn MessagePublisher>,
     job: &Job,
-    mut job_result: JobResult,
+    job_result: JobResult,
+    delivery: &DeliveryInfo,
+    secret_values: &mut SecretValues,
+    fixture_parameters: &mut Option<serde_json::Value>,
   ) -> Result<JobResult, MessageError> {
+    let result_cache_key = if config::result_cache() {
+      Some(result_cache::get_key(job, &self.read_python_file()))
+    } else {
+      None
+    };
+    if let Some(cached_result) = result_cache_key
+      .as_ref()
+      .and_then(|key| result_cache::get(key, job))
+    {
+      info!(
+        "job {}: identical parameters, use the cached result",
+        job.job_id
+      );
+      return Ok(cached_result);
+    }
+
+    let queued_guard = health::queue_job();
+
+    // stateful workers must never see two jobs at the same time
+    let _serial_guard = if self.is_reentrant() {
+      None
+    } else {
+      Some(
+        SERIAL_PROCESS_LOCK
+          .lock()
+          .unwrap_or_else(|error| error.into_inner()),
+      )
+    };
+
+    // jobs targeting the same resource are limited, whatever the other jobs are doing
+    let _resource_guard = config::resource_parameter()
+      .and_then(|identifier| get_parameter_value(job, &identifier))
+      .map(|resource| RESOURCE_SEMAPHORE.acquire(&resource, config::resource_limit()));
+
+    drop(queued_guard);
+    let _in_flight_guard = health::start_job();
+
+    let parameters_description = self.get_parameters_description();
     let contents = self.read_python_file();
+    let _job_span = telemetry::start_job_span(job);
 
     let gil = Python::acquire_gil();
     let py = gil.python();
-    let traceback = py.import("traceback").unwrap();
-    let python_module = PyModule::from_code(py, &contents, "worker.py", "worker")
+    let python_module = interpreter::load_module(py, &contents, config::fresh_module())
       .expect("unable to create the python module");
 
+    // the inputs downloaded while building the parameters are removed once the job is processed
+    let _downloaded_inputs_guard = inputs::remove_downloaded_inputs();
     let list_of_parameters = PyDict::new(py);
-    if let Err(error) = self.build_parameters(job, py, list_of_parameters) {
-      let locals = [("error", error)].into_py_dict(py);
+    let parameters = self.build_parameters(
+      job,
+      py,
+      list_of_parameters,
+      &parameters_description,
+      secret_values,
+    );
+    // recorded before preprocess, which runs again when the fixture is replayed
+    if config::fixture_directory().is_some() {
+      *fixture_parameters = Some(fixtures::get_fixture_parameters(
+        job,
+        list_of_parameters,
+        &parameters_description,
+      ));
+    }
+    match parameters {
+      Ok(validation_errors) => {
+        if !validation_errors.is_empty() {
+          let validation_errors = secret_values.redact_validation_errors(validation_errors);
+          let message = validation::get_message(&validation_errors);
+          let result = validation::get_validation_failure(job_result, &validation_errors);
+          let result = failure::with_failure_context(result, self, "ValidationError", &message);
+          return Err(MessageError::ProcessingError(result));
+        }
+      }
+      Err(error) => {
+        let error_type = error.ptype.as_ref(py).name().to_string();
+        let locals = [("error", error)].into_py_dict(py);
+
+        let error_msg = py
+          .eval("repr(error)", None, Some(locals))
+          .expect("Unknown python error, unable to get the error message")
+          .to_string();
+        let error_msg = secret_values.redact(&error_msg);
+
+        let result = job_result
+          .with_status(JobStatus::Error)
+          .with_message(&error_msg);
+        let result = failure::with_failure_context(result, self, &error_type, &error_msg);
+        return Err(MessageError::ProcessingError(result));
+      }
+    }
 
-      let error_msg = py
-        .eval("repr(error)", None, Some(locals))
-        .expect("Unknown python error, unable to get the error message")
-        .to_string();
+    let list_of_parameters = match interpreter::preprocess(python_module, list_of_parameters) {
+      Ok(list_of_parameters) => list_of_parameters,
+      Err(error) => {
+        error!("unable to preprocess the parameters of job {}", job.job_id);
+        return self.handle_process_error(py, error, job_result, secret_values);
+      }
+    };
 
+    let job_state = Arc::new(JobState::default());
+    let job_log_path = if config::job_log_file() {
+      let path = config::output_directory()
+        .unwrap_or_default()
+        .join(format!("job_{}.log", job.job_id));
+      match fs::File::create(&path) {
+        Ok(log_file) => {
+          job_state.set_log_file(log_file);
+          job_state.write_log("INFO", &format!("job {} started", job.job_id));
+          Some(path.to_string_lossy().to_string())
+        }
+        Err(error) => {
+          warn!("unable to create the job log {}: {}", path.display(), error);
+          None
+        }
+      }
+    } else {
+      None
+    };
+    let _job_log_guard = job_log_path
+      .as_ref()
+      .map(|_| logger::capture_job_log(job_state.clone()));
+    let callback_handle = CallbackHandle::new(
+      publisher,
+      job.clone(),
+      job_state.clone(),
+      parameters_description.clone(),
+      delivery.priority,
+    );
+
+    let process_sample = process_stats::sample();
+    let started = Instant::now();
+    let response = python_module
+      .call1("process", (callback_handle, list_of_parameters))
+      .and_then(|response| interpreter::complete_coroutine(py, response));
+    process_stats::check_leaks(job.job_id, process_sample, process_stats::sample());
+
+    let destination_paths: Vec<String> = response
+      .as_ref()
+      .ok()
+      .and_then(|response| get_destinations(response))
+      .unwrap_or_default()
+      .into_iter()
+      .map(|destination| destination.path)
+      .collect();
+    let cleanup_paths = response
+      .as_ref()
+      .ok()
+      .and_then(|response| get_string_list(response, "cleanup_paths"));
+
+    // the consumer publishes the message to the deferred queue, it comes back after the delay
+    if let Some(deferral) = job_state.take_deferral() {
+      info!(
+        "job {} deferred for {}s: {}",
+        job.job_id,
+        deferral.delay.as_secs(),
+        deferral.reason
+      );
+      // a deferred batch would process its other jobs again
+      if delivery.batch_item {
+        let result = job_result
+          .with_status(JobStatus::Error)
+          .with_message(&format!(
+            "a job of a batch cannot be deferred: {}",
+            deferral.reason
+          ));
+        return Err(MessageError::ProcessingError(result));
+      }
+      let reason = format!("job deferred: {}", deferral.reason);
+      delivery.defer(deferral);
+      return Err(MessageError::RequirementsError(reason));
+    }
+
+    let result = if let Some(failure) = job_state.take_failure() {
       let result = job_result
         .with_status(JobStatus::Error)
-        .with_message(&error_msg);
-      return Err(MessageError::ProcessingError(result));
+        .with_message(&secret_values.redact(&failure.message));
+      let result = with_string_parameter(result, "error_code", &failure.code);
+      let result = failure::with_failure_context(
+        result,
+        self,
+        &failure.code,
+        &secret_values.redact(&failure.message),
+      );
+      Err(MessageError::ProcessingError(result))
+    } else {
+      match response {
+        // the outputs are uploaded only once the response is known to be valid
+        Ok(response) => {
+          match self.handle_process_response(response, job_result.clone(), secret_values) {
+            Ok(completed) => {
+              match upload::upload(py, python_module, &destination_paths, list_of_parameters) {
+                Ok(()) => Ok(completed),
+                Err(error) => {
+                  error!("unable to upload the outputs of job {}", job.job_id);
+                  self.handle_process_error(py, error, job_result, secret_values)
+                }
+              }
+            }
+            Err(error) => Err(error),
+          }
+        }
+        Err(error) => self.handle_process_error(py, error, job_result, secret_values),
+      }
+    };
+    let result = duration::check_slow_job(job, started.elapsed(), result);
+
+    if let (Ok(_), Some(cleanup_paths)) = (&result, cleanup_paths) {
+      delivery.cleanup_after_ack(cleanup_paths);
     }
 
-    let callback_handle = CallbackHandle {
-      channel: channel.unwrap().clone(),
-      job: job.clone(),
+    let result = match job_log_path {
+      Some(job_log_path) => {
+        let status = match &result {
+          Ok(_) => "completed".to_string(),
+          Err(MessageError::ProcessingError(_)) => "failed".to_string(),
+          Err(error) => format!("stopped: {:?}", error),
+        };
+        job_state.write_log("INFO", &format!("job {} {}", job.job_id, status));
+        map_job_result(result, |job_result| {
+          with_string_parameter(job_result, "job_log_file", &job_log_path)
+            .with_destination_paths(&mut vec![job_log_path.clone()])
+        })
+      }
+      None => result,
+    };
+
+    let result = match job_state.take_media_info() {
+      Some(media_info) => map_job_result(result, |job_result| {
+        with_json_parameter(job_result, "media_info", &media_info)
+      }),
+      None => result,
+    };
+
+    let annotations = job_state.take_annotations();
+    let result = if annotations.is_empty() {
+      result
+    } else {
+      map_job_result(result, |job_result| {
+        with_json_parameter(
+          job_result,
+          "annotations",
+          &serde_json::Value::Object(annotations),
+        )
+      })
+    };
+
+    let recorded_errors = job_state.take_recorded_errors();
+    let result = if recorded_errors.is_empty() {
+      result
+    } else {
+      let recorded_errors: Vec<serde_json::Value> = recorded_errors
+        .iter()
+        .map(|error| {
+          serde_json::json!({
+            "code": error.code,
+            "message": secret_values.redact(&error.message),
+          })
+        })
+        .collect();
+      map_job_result(result, |job_result| {
+        with_json_parameter(
+          job_result,
+          "recorded_errors",
+          &serde_json::Value::Array(recorded_errors),
+        )
+      })
     };
 
-    match python_module.call1("process", (callback_handle, list_of_parameters)) {
-      Ok(response) => {
-        if let Some(mut destination_paths) = get_destination_paths(response) {
-          job_result = job_result.with_destination_paths(&mut destination_paths);
+    if let (Some(key), Ok(job_result)) = (&result_cache_key, &result) {
+      result_cache::set(key, job_result, &destination_paths);
+    }
+
+    result
+  }
+
+  fn handle_process_response(
+    &self,
+    response: &PyAny,
+    mut job_result: JobResult,
+    secret_values: &SecretValues,
+  ) -> Result<JobResult, MessageError> {
+    // returning None is the usual way to complete a job without outputs
+    if !response.is_none() && response.downcast_ref::<PyDict>().is_err() {
+      let message = format!(
+        "process returned a {}, expected a dict or None",
+        response.get_type().name()
+      );
+      if config::strict_return() {
+        let result = job_result
+          .with_status(JobStatus::Error)
+          .with_message(&secret_values.redact(&message));
+        return Err(MessageError::ProcessingError(result));
+      }
+      warn!("{}, the response is ignored", message);
+    }
+
+    if config::validate_outputs() {
+      if let Some(descriptions) = self.get_output_description() {
+        if let Err(message) =
+          outputs::check_output_schema(response, &descriptions, &config::json_options())
+        {
+          let result = job_result
+            .with_status(JobStatus::Error)
+            .with_message(&secret_values.redact(&message));
+          return Err(MessageError::ProcessingError(result));
         }
+      }
+    }
 
-        Ok(job_result.with_status(JobStatus::Completed))
+    if config::strict_return() {
+      let unknown_keys = outputs::get_unknown_keys(response);
+      if !unknown_keys.is_empty() {
+        let result = job_result
+          .with_status(JobStatus::Error)
+          .with_message(&secret_values.redact(&format!(
+            "unknown keys returned by process: {}",
+            unknown_keys.join(", ")
+          )));
+        return Err(MessageError::ProcessingError(result));
       }
-      Err(error) => {
-        let stacktrace = if let Some(tb) = &error.ptraceback {
-          let locals = [("traceback", traceback)].into_py_dict(py);
+    }
 
-          locals.set_item("tb", tb).unwrap();
+    if let Some(destinations) = get_destinations(response) {
+      let mut destination_paths: Vec<String> = destinations
+        .iter()
+        .map(|destination| destination.path.clone())
+        .collect();
 
-          py.eval("traceback.format_tb(tb)", None, Some(locals))
-            .expect("Unknown python error, unable to get the stacktrace")
-            .to_string()
-        } else {
-          "Unknown python error, no stackstrace".to_string()
-        };
+      if let Some(max_outputs) = config::max_outputs() {
+        if destination_paths.len() > max_outputs {
+          let result =
+            job_result
+              .with_status(JobStatus::Error)
+              .with_message(&secret_values.redact(&format!(
+                "too many destination paths: {}, at most {} are allowed",
+                destination_paths.len(),
+                max_outputs
+              )));
+          return Err(MessageError::ProcessingError(result));
+        }
+      }
 
-        let locals = [("error", error)].into_py_dict(py);
+      if let Some(max_output_bytes) = config::max_output_bytes() {
+        let total_size = outputs::get_total_size(&destination_paths);
+        if total_size > max_output_bytes {
+          let result =
+            job_result
+              .with_status(JobStatus::Error)
+              .with_message(&secret_values.redact(&format!(
+                "destination paths too large: {} bytes, at most {} bytes are allowed",
+                total_size, max_output_bytes
+              )));
+          return Err(MessageError::ProcessingError(result));
+        }
+      }
 
-        let error_msg = py
-          .eval("repr(error)", None, Some(locals))
-          .expect("Unknown python error, unable to get the error message")
-          .to_string();
+      if config::verify_outputs() {
+        let missing_paths = get_missing_paths(&destination_paths);
+        if !missing_paths.is_empty() {
+          let result =
+            job_result
+              .with_status(JobStatus::Error)
+              .with_message(&secret_values.redact(&format!(
+                "missing destination paths: {}",
+                missing_paths.join(", ")
+              )));
+          return Err(MessageError::ProcessingError(result));
+        }
+      }
+
+      if config::hash_outputs() {
+        let digests = outputs::get_digests(&destination_paths);
+        job_result = with_json_parameter(job_result, "destination_sha256", &digests);
+      }
+
+      if config::report_sizes() {
+        let sizes = outputs::get_sizes(&destination_paths);
+        job_result = with_json_parameter(job_result, "destination_sizes", &sizes);
+      }
 
-        let error_message = format!("{}\n\nStacktrace:\n{}", error_msg, stacktrace);
+      let mime_types = outputs::get_mime_types(&destinations, config::infer_mime_types());
+      if !mime_types.is_empty() {
+        job_result = with_json_parameter(
+          job_result,
+          "destination_mime_types",
+          &serde_json::Value::Object(mime_types),
+        );
+      }
 
+      job_result = job_result.with_destination_paths(&mut destination_paths);
+    }
+
+    match outputs::get_output_parameters(response, &config::json_options()) {
+      Ok(mut output_parameters) => job_result = job_result.with_parameters(&mut output_parameters),
+      Err(message) => {
         let result = job_result
           .with_status(JobStatus::Error)
-          .with_message(&error_message);
-        Err(MessageError::ProcessingError(result))
+          .with_message(&secret_values.redact(&message));
+        return Err(MessageError::ProcessingError(result));
       }
     }
+
+    Ok(job_result.with_status(JobStatus::Completed))
+  }
+
+  fn handle_process_error(
+    &self,
+    py: Python,
+    error: PyErr,
+    job_result: JobResult,
+    secret_values: &SecretValues,
+  ) -> Result<JobResult, MessageError> {
+    // these exceptions would stop the interpreter, only the job fails
+    if error.is_instance::<SystemExit>(py) || error.is_instance::<KeyboardInterrupt>(py) {
+      let error_type = error.ptype.as_ref(py).name().to_string();
+      let arguments = error
+        .to_object(py)
+        .getattr(py, "args")
+        .and_then(|arguments| {
+          arguments
+            .as_ref(py)
+            .repr()
+            .map(|arguments| arguments.to_string_lossy().to_string())
+        })
+        .unwrap_or_default();
+      let message = format!(
+        "process raised {}{}, the job is stopped but the worker keeps running",
+        error_type, arguments
+      );
+      warn!("{}", message);
+
+      let result = job_result
+        .with_status(JobStatus::Error)
+        .with_message(&message);
+      let result = failure::with_failure_context(result, self, &error_type, &message);
+      return Err(MessageError::ProcessingError(result));
+    }
+
+    let traceback = py.import("traceback").unwrap();
+    let error_type = error.ptype.as_ref(py).name().to_string();
+
+    let stacktrace = if let Some(tb) = &error.ptraceback {
+      let locals = [("traceback", traceback)].into_py_dict(py);
+
+      locals.set_item("tb", tb).unwrap();
+
+      py.eval("traceback.format_tb(tb)", None, Some(locals))
+        .expect("Unknown python error, unable to get the stacktrace")
+        .to_string()
+    } else {
+      "Unknown python error, no stackstrace".to_string()
+    };
+
+    let locals = [("error", error)].into_py_dict(py);
+
+    let error_msg = py
+      .eval("repr(error)", None, Some(locals))
+      .expect("Unknown python error, unable to get the error message")
+      .to_string();
+
+    let error_message =
+      secret_values.redact(&format!("{}\n\nStacktrace:\n{}", error_msg, stacktrace));
+
+    let result = job_result
+      .with_status(JobStatus::Error)
+      .with_message(&error_message);
+    let result = failure::with_failure_context(result, self, &error_type, &error_message);
+    Err(MessageError::ProcessingError(result))
   }
-}
 
-impl PythonWorkerEvent {
   fn build_parameters(
     &self,
     job: &Job,
     py: Python,
     list_of_parameters: &PyDict,
-  ) -> Result<(), PyErr> {
+    parameters_description: &[ParameterDescription],
+    secret_values: &mut SecretValues,
+  ) -> PyResult<Vec<ValidationError>> {
+    let mut validation_errors = vec![];
+
     for parameter in &job.parameters {
       match parameter {
         ArrayOfStringsParam { id, default, value } => {
-          if let Some(v) = value {
-            list_of_parameters.set_item(id.to_string(), PyList::new(py, v))?;
-          } else if let Some(v) = default {
-            list_of_parameters.set_item(id.to_string(), PyList::new(py, v))?;
+          let description = find_description(parameters_description, id);
+          if let Some(v) = value.as_ref().or(default.as_ref()) {
+            match kinds::convert(py, description, &serde_json::Value::from(v.clone())) {
+              Ok(converted) => list_of_parameters.set_item(id.to_string(), converted)?,
+              Err(validation_error) => validation_errors.push(validation_error),
+            }
           }
         }
         BooleanParam { id, default, value } => {
-          if let Some(v) = value {
-            list_of_parameters.set_item(id.to_string(), v)?;
-          } else if let Some(v) = default {
-            list_of_parameters.set_item(id.to_string(), v)?;
+          let description = find_description(parameters_description, id);
+          if let Some(v) = value.or(*default) {
+            match kinds::convert(py, description, &serde_json::Value::from(v)) {
+              Ok(converted) => list_of_parameters.set_item(id.to_string(), converted)?,
+              Err(validation_error) => validation_errors.push(validation_error),
+            }
           }
         }
         CredentialParam { id, default, value } => {
@@ -244,45 +858,329 @@ impl PythonWorkerEvent {
             None
           };
 
+          let description = find_description(parameters_description, id);
+          let required = description
+            .map(|description| description.required)
+            .unwrap_or(false);
+          let sensitive_fields = description
+            .and_then(|description| description.sensitive_fields.as_ref())
+            .map(Vec::as_slice);
+
           if let Some(credential_key) = credential_key {
-            let credential = amqp_worker::Credential {
-              key: credential_key.to_string(),
-            };
-            if let Ok(retrieved_value) = credential.request_value(&job) {
-              list_of_parameters.set_item(id.to_string(), retrieved_value)?;
-            } else {
-              error!("unable to retrieve the credential value");
+            match secret::request_credential(credential_key, job) {
+              Ok(retrieved_value) => {
+                secret_values.add_credential(&retrieved_value, sensitive_fields);
+                list_of_parameters.set_item(id.to_string(), retrieved_value)?;
+              }
+              Err(message) if required => validation_errors.push(ValidationError::new(
+                ValidationErrorCode::CredentialUnavailable,
+                id,
+                message,
+              )),
+              Err(message) => error!("{}", message),
             }
           } else {
             error!("no value or default for the credential value");
           }
         }
         IntegerParam { id, default, value } => {
-          if let Some(v) = value {
-            list_of_parameters.set_item(id.to_string(), v)?;
-          } else if let Some(v) = default {
-            list_of_parameters.set_item(id.to_string(), v)?;
+          let description = find_description(parameters_description, id);
+          if let Some(v) = value.or(*default) {
+            match kinds::convert(py, description, &serde_json::Value::from(v)) {
+              Ok(converted) => list_of_parameters.set_item(id.to_string(), converted)?,
+              Err(validation_error) => validation_errors.push(validation_error),
+            }
           }
         }
         RequirementParam { .. } => {
           // do nothing
         }
         StringParam { id, default, value } => {
-          if let Some(v) = value {
-            list_of_parameters.set_item(id.to_string(), v)?;
-          } else if let Some(v) = default {
-            list_of_parameters.set_item(id.to_string(), v)?;
+          let description = find_description(parameters_description, id);
+          let is_secret_reference = description
+            .map(|description| description.has_kind("secret_ref"))
+            .unwrap_or(false);
+
+          if is_secret_reference {
+            if let Some(reference) = value.as_ref().or(default.as_ref()) {
+              match secret::resolve_secret(reference, job) {
+                Ok(retrieved_value) => {
+                  secret_values.add(&retrieved_value);
+                  list_of_parameters.set_item(id.to_string(), retrieved_value)?;
+                }
+                Err(message) if description.map(|d| d.required).unwrap_or(false) => {
+                  validation_errors.push(ValidationError::new(
+                    ValidationErrorCode::CredentialUnavailable,
+                    id,
+                    message,
+                  ))
+                }
+                Err(message) => error!("{}", message),
+              }
+            } else {
+              error!("no value or default for the secret reference");
+            }
+          } else if let Some(v) = value.as_ref().or(default.as_ref()) {
+            match kinds::convert(py, description, &serde_json::Value::from(v.as_str())) {
+              Ok(converted) => list_of_parameters.set_item(id.to_string(), converted)?,
+              Err(validation_error) => validation_errors.push(validation_error),
+            }
           }
         }
       }
     }
 
-    Ok(())
+    for description in parameters_description {
+      let reported = validation_errors
+        .iter()
+        .any(|validation_error| validation_error.parameter == description.identifier);
+
+      if description.required
+        && description.oneof.is_none()
+        && !description.has_kind("requirement")
+        && !reported
+        && list_of_parameters
+          .get_item(description.identifier.as_str())
+          .is_none()
+      {
+        validation_errors.push(ValidationError::new(
+          ValidationErrorCode::MissingRequired,
+          &description.identifier,
+          "missing required parameter".to_string(),
+        ));
+      }
+    }
+
+    output_profile::set_output_profile(py, job, list_of_parameters)?;
+
+    validation_errors.extend(validation::check_dependencies(
+      parameters_description,
+      |identifier| {
+        list_of_parameters
+          .get_item(identifier)
+          .and_then(|value| py_to_json(value).ok())
+      },
+    ));
+    validation_errors.extend(validation::check_exclusive_groups(
+      parameters_description,
+      |identifier| list_of_parameters.get_item(identifier).is_some(),
+    ));
+
+    Ok(validation_errors)
   }
 }
 
 static PYTHON_WORKER_EVENT: PythonWorkerEvent = PythonWorkerEvent {};
 
 fn main() {
-  start_worker(&PYTHON_WORKER_EVENT);
+  logger::init();
+  telemetry::flush_on_panic();
+  telemetry::shutdown_on_signal();
+  interpreter::configure();
+  secret::register_resolver(Arc::new(secret::CredentialResolver {}));
+  inputs::register_resolver(Arc::new(inputs::HttpResolver {}));
+  inputs::register_resolver(Arc::new(inputs::FileResolver {}));
+
+  if env::args().any(|argument| argument == "--describe") {
+    let descriptor = describe::get_descriptor(&PYTHON_WORKER_EVENT);
+    println!("{}", serde_json::to_string_pretty(&descriptor).unwrap());
+    return;
+  }
+
+  if env::args().any(|argument| argument == "--register") {
+    let payload = registration::get_registration_payload(&PYTHON_WORKER_EVENT);
+    match config::registry_url() {
+      Some(url) => {
+        if let Err(error) = registration::register(&url, &payload) {
+          eprintln!("unable to register the worker to {}: {}", url, error);
+          std::process::exit(1);
+        }
+        println!("the worker is registered to {}", url);
+      }
+      None => println!("{}", serde_json::to_string_pretty(&payload).unwrap()),
+    }
+    return;
+  }
+
+  let arguments: Vec<String> = env::args().collect();
+  if let Some(position) = arguments
+    .iter()
+    .position(|argument| argument == "--assert-schema")
+  {
+    let filename = arguments
+      .get(position + 1)
+      .expect("missing expected schema file after --assert-schema");
+    let expected = fs::read_to_string(filename)
+      .ok()
+      .and_then(|contents| serde_json::from_str(&contents).ok())
+      .unwrap_or_else(|| panic!("unable to read the expected schema from {}", filename));
+
+    let differences = describe::get_schema_differences(&PYTHON_WORKER_EVENT, &expected);
+    if differences.is_empty() {
+      println!("the parameters match {}", filename);
+      return;
+    }
+    for difference in &differences {
+      println!("{}", difference);
+    }
+    std::process::exit(1);
+  }
+
+  let mut mismatches = signatures::check_signatures(&PYTHON_WORKER_EVENT.read_python_file());
+  if mismatches.is_empty() {
+    mismatches.extend(
+      PYTHON_WORKER_EVENT
+        .get_parameters_description()
+        .iter()
+        .filter(|description| !description.is_default_compatible())
+        .map(|description| {
+          format!(
+            "the default of {} is not compatible with its kind {}",
+            description.identifier,
+            description.kind.join(", ")
+          )
+        }),
+    );
+  }
+  if !mismatches.is_empty() {
+    for mismatch in &mismatches {
+      error!("{}: {}", config::filename(), mismatch);
+    }
+    panic!(
+      "{} does not implement a valid worker: {}",
+      config::filename(),
+      mismatches.join(", ")
+    );
+  }
+
+  if let Some(port) = config::health_port() {
+    health::start_endpoint(port);
+  }
+
+  info!(
+    "python worker configuration: {}",
+    config::get_effective_configuration()
+  );
+  info!(
+    "python worker source: {}",
+    describe::get_source_version(&PYTHON_WORKER_EVENT)
+  );
+  consumer::start_worker(&PYTHON_WORKER_EVENT);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::helpers::get_result_json;
+
+  fn get_result_message(result: &Result<JobResult, MessageError>) -> Option<String> {
+    get_result_json(result)["parameters"]
+      .as_array()?
+      .iter()
+      .find(|parameter| parameter["id"] == "message")
+      .and_then(|parameter| parameter["value"].as_str())
+      .map(|message| message.to_string())
+  }
+
+  fn raise(py: Python, statement: &str) -> PyErr {
+    py.run(statement, None, None).unwrap_err()
+  }
+
+  #[test]
+  fn required_requirement_is_not_missing() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let job = Job::new(
+      r#"{
+        "job_id": 230,
+        "parameters": [
+          {"id": "requirements", "type": "requirements", "value": {"paths": []}}
+        ]
+      }"#,
+    )
+    .unwrap();
+    let parameters_description = vec![ParameterDescription {
+      identifier: "requirements".to_string(),
+      label: "Requirements".to_string(),
+      kind: vec!["requirement".to_string()],
+      required: true,
+      ..Default::default()
+    }];
+
+    let validation_errors = PYTHON_WORKER_EVENT
+      .build_parameters(
+        &job,
+        py,
+        PyDict::new(py),
+        &parameters_description,
+        &mut SecretValues::default(),
+      )
+      .unwrap();
+    assert!(validation_errors.is_empty(), "{:?}", validation_errors);
+  }
+
+  #[test]
+  fn none_response_completes_the_job_without_outputs() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let response = py.eval("None", None, None).unwrap();
+
+    assert!(get_destinations(response).unwrap_or_default().is_empty());
+    let result = PYTHON_WORKER_EVENT.handle_process_response(
+      response,
+      JobResult::new(3),
+      &SecretValues::default(),
+    );
+    match &result {
+      Ok(job_result) => assert_eq!(*job_result.get_status(), JobStatus::Completed),
+      _ => panic!("unexpected result: {:?}", result),
+    }
+    let result = get_result_json(&result);
+    assert!(result["destination_paths"]
+      .as_array()
+      .map(|paths| paths.is_empty())
+      .unwrap_or(true));
+  }
+
+  #[test]
+  fn system_exit_fails_the_job_only() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    let error = raise(py, "raise SystemExit(3)");
+    let result = PYTHON_WORKER_EVENT.handle_process_error(
+      py,
+      error,
+      JobResult::new(1),
+      &SecretValues::default(),
+    );
+
+    match &result {
+      Err(MessageError::ProcessingError(job_result)) => {
+        assert_eq!(*job_result.get_status(), JobStatus::Error)
+      }
+      _ => panic!("unexpected result: {:?}", result),
+    }
+    let message = get_result_message(&result).unwrap();
+    assert!(message.contains("SystemExit(3,)"), "{}", message);
+    assert!(message.contains("the worker keeps running"), "{}", message);
+  }
+
+  #[test]
+  fn keyboard_interrupt_fails_the_job_only() {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+
+    let error = raise(py, "raise KeyboardInterrupt()");
+    let result = PYTHON_WORKER_EVENT.handle_process_error(
+      py,
+      error,
+      JobResult::new(2),
+      &SecretValues::default(),
+    );
+
+    assert!(matches!(result, Err(MessageError::ProcessingError(_))));
+    let message = get_result_message(&result).unwrap();
+    assert!(message.contains("KeyboardInterrupt()"), "{}", message);
+  }
 }
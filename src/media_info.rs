@@ -0,0 +1,40 @@
+use serde_json::Value;
+
+/// Known media characteristics, with their expected type.
+const FIELDS: &[(&str, &str)] = &[
+  ("duration", "number"),
+  ("width", "integer"),
+  ("height", "integer"),
+  ("codec", "string"),
+  ("frame_rate", "number"),
+  ("bit_rate", "integer"),
+  ("sample_rate", "integer"),
+  ("channels", "integer"),
+];
+
+/// Checks the type of the known characteristics, the other ones are kept as they are.
+pub fn check_media_info(info: &Value) -> Result<(), String> {
+  let info = info
+    .as_object()
+    .ok_or_else(|| "media info must be a dict".to_string())?;
+
+  for (field, expected) in FIELDS {
+    let value = match info.get(*field) {
+      Some(Value::Null) | None => continue,
+      Some(value) => value,
+    };
+
+    let valid = match *expected {
+      "number" => value.as_f64().map(|number| number >= 0.0).unwrap_or(false),
+      "integer" => value.is_u64(),
+      _ => value.is_string(),
+    };
+    if !valid {
+      return Err(format!(
+        "invalid media info {}: expected a positive {}, got {}",
+        field, expected, value
+      ));
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,455 @@
+use crate::{config, consumer};
+use amqp_worker::job::{Job, JobProgression};
+use flate2::{write::GzEncoder, Compression};
+use futures::{
+  executor::{self, Notify},
+  Async, Future,
+};
+use lapin::Connection;
+use lapin_futures::{
+  options::{BasicPublishOptions, ConfirmSelectOptions},
+  BasicProperties, Channel, ConfirmationFuture, ConnectionProperties,
+};
+use serde_json::{json, Value};
+use std::{
+  io::Write,
+  sync::{Arc, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+
+const RESPONSE_EXCHANGE: &str = "job_response";
+
+/// Attempts to publish a message, when publisher confirms are enabled.
+const CONFIRMED_PUBLISH_ATTEMPTS: usize = 3;
+
+/// Delay after which a message which is neither acked nor returned is considered nacked.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Destination of the messages emitted while a job is processed, and of its result.
+pub trait MessagePublisher: Send + Sync {
+  fn publish(&self, routing_key: &str, message: String) -> Result<(), String> {
+    self.publish_response(
+      routing_key,
+      message.into_bytes(),
+      BasicProperties::default(),
+    )
+  }
+
+  /// Publishes a message to the response exchange with its properties, e.g. a compressed result.
+  fn publish_response(
+    &self,
+    routing_key: &str,
+    payload: Vec<u8>,
+    properties: BasicProperties,
+  ) -> Result<(), String>;
+
+  /// Publishes a job message to a queue of the worker, e.g. the dead-letter or the deferred queue.
+  fn publish_to_queue(
+    &self,
+    queue: &str,
+    message: String,
+    properties: BasicProperties,
+  ) -> Result<(), String>;
+
+  fn publish_job_progression(&self, job: &Job, progression: u8) -> Result<(), String> {
+    let message = json!(JobProgression::new(job, progression));
+    self.publish("job_progression", message.to_string())
+  }
+}
+
+pub struct ChannelPublisher {
+  channel: Mutex<Channel>,
+  /// Connection opened by the publisher once the connection of the consumer was lost.
+  connection: Mutex<Option<Connection>>,
+  /// Last progression of the job in flight, published again once connected again.
+  last_progression: Mutex<Option<String>>,
+}
+
+struct ThreadNotify(thread::Thread);
+
+impl Notify for ThreadNotify {
+  fn notify(&self, _id: usize) {
+    self.0.unpark();
+  }
+}
+
+/// Waits for the future on the current thread, it is dropped when the timeout expires.
+fn wait_with_timeout<F: Future>(future: F, timeout: Duration) -> Option<Result<F::Item, F::Error>> {
+  let notify = Arc::new(ThreadNotify(thread::current()));
+  let deadline = Instant::now() + timeout;
+  let mut future = executor::spawn(future);
+
+  loop {
+    match future.poll_future_notify(&notify, 0) {
+      Ok(Async::Ready(item)) => return Some(Ok(item)),
+      Ok(Async::NotReady) => {
+        let now = Instant::now();
+        if now >= deadline {
+          return None;
+        }
+        thread::park_timeout(deadline - now);
+      }
+      Err(error) => return Some(Err(error)),
+    }
+  }
+}
+
+/// With `PYTHON_WORKER_PUBLISHER_CONFIRMS`, the channel is put in confirm mode, so it must be
+/// dedicated to the publisher.
+fn select_confirms(channel: Channel) -> Channel {
+  if config::publisher_confirms() {
+    if let Err(error) = channel
+      .confirm_select(ConfirmSelectOptions::default())
+      .wait()
+    {
+      warn!("unable to enable the publisher confirms: {}", error);
+    }
+  }
+  channel
+}
+
+impl ChannelPublisher {
+  pub fn new(channel: Channel) -> Self {
+    ChannelPublisher {
+      channel: Mutex::new(select_confirms(channel)),
+      connection: Mutex::new(None),
+      last_progression: Mutex::new(None),
+    }
+  }
+
+  fn channel(&self) -> Channel {
+    self
+      .channel
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .clone()
+  }
+
+  /// Publishes on a new connection, and publishes again the last progression of the job in flight
+  /// so the orchestrator catches up with the progressions lost meanwhile.
+  fn reconnect(&self) -> Result<(), String> {
+    let connection =
+      Connection::connect_uri(consumer::get_amqp_uri(), ConnectionProperties::default())
+        .wait()
+        .map_err(|error| error.to_string())?;
+    let channel = Channel::create(&connection)
+      .wait()
+      .map_err(|error| error.to_string())?;
+
+    let previous_channel = std::mem::replace(
+      &mut *self
+        .channel
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()),
+      select_confirms(channel),
+    );
+    let previous_connection = self
+      .connection
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .replace(connection);
+    // the broker may not answer on a lost connection, so the close is not awaited longer
+    match previous_connection {
+      Some(previous_connection) => {
+        let closed: ConfirmationFuture<()> = previous_connection.close(200, "reconnected").into();
+        wait_with_timeout(closed, CONFIRM_TIMEOUT);
+      }
+      None => {
+        wait_with_timeout(previous_channel.close(200, "reconnected"), CONFIRM_TIMEOUT);
+      }
+    }
+    info!("connected again to publish the job messages");
+
+    let last_progression = self
+      .last_progression
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .clone();
+    if let Some(message) = last_progression {
+      info!("publish again the last job progression: {}", message);
+      self.publish_to(
+        RESPONSE_EXCHANGE,
+        "job_progression",
+        message.as_bytes(),
+        BasicProperties::default(),
+      )?;
+    }
+    Ok(())
+  }
+
+  fn publish_or_reconnect(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    payload: &[u8],
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    self
+      .publish_to(exchange, routing_key, payload, properties.clone())
+      .or_else(|error| {
+        warn!(
+          "unable to publish to {}, connecting again: {}",
+          routing_key, error
+        );
+        self.reconnect()?;
+        if exchange == RESPONSE_EXCHANGE && routing_key == "job_progression" {
+          // it is the last progression, already published again
+          return Ok(());
+        }
+        self.publish_to(exchange, routing_key, payload, properties)
+      })
+  }
+
+  fn publish_once(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    payload: &[u8],
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    let options = BasicPublishOptions {
+      // an unroutable message is returned by the broker instead of being dropped
+      mandatory: config::publisher_confirms(),
+      ..Default::default()
+    };
+    self
+      .channel()
+      .basic_publish(exchange, routing_key, payload.to_vec(), options, properties)
+      .wait()
+      .map_err(|error| error.to_string())
+  }
+
+  /// A nacked message is never confirmed, so the wait is bounded.
+  fn wait_for_confirms(&self) -> Result<(), String> {
+    match wait_with_timeout(self.channel().wait_for_confirms(), CONFIRM_TIMEOUT) {
+      Some(Ok(returned_messages)) if returned_messages.is_empty() => Ok(()),
+      Some(Ok(returned_messages)) => Err(format!(
+        "{} message(s) returned by the broker",
+        returned_messages.len()
+      )),
+      Some(Err(error)) => Err(error.to_string()),
+      None => Err(format!(
+        "not confirmed after {}s",
+        CONFIRM_TIMEOUT.as_secs()
+      )),
+    }
+  }
+
+  /// A message which is not confirmed by the broker is published again.
+  fn publish_to(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    payload: &[u8],
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    if !config::publisher_confirms() {
+      return self.publish_once(exchange, routing_key, payload, properties);
+    }
+
+    let mut last_error = String::new();
+    for attempt in 1..=CONFIRMED_PUBLISH_ATTEMPTS {
+      match self
+        .publish_once(exchange, routing_key, payload, properties.clone())
+        .and_then(|()| self.wait_for_confirms())
+      {
+        Ok(()) => return Ok(()),
+        Err(error) => {
+          warn!(
+            "message to {} not confirmed (attempt {} of {}): {}",
+            routing_key, attempt, CONFIRMED_PUBLISH_ATTEMPTS, error
+          );
+          last_error = error;
+        }
+      }
+    }
+    Err(last_error)
+  }
+}
+
+impl MessagePublisher for ChannelPublisher {
+  fn publish_response(
+    &self,
+    routing_key: &str,
+    payload: Vec<u8>,
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    {
+      let mut last_progression = self
+        .last_progression
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+      match routing_key {
+        "job_progression" => {
+          *last_progression = Some(String::from_utf8_lossy(&payload).to_string())
+        }
+        "job_completed" | "job_error" => *last_progression = None,
+        _ => {}
+      }
+    }
+
+    self.publish_or_reconnect(RESPONSE_EXCHANGE, routing_key, &payload, properties)
+  }
+
+  fn publish_to_queue(
+    &self,
+    queue: &str,
+    message: String,
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    self.publish_or_reconnect("", queue, message.as_bytes(), properties)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishedMessage {
+  pub routing_key: String,
+  pub payload: Vec<u8>,
+  pub properties: BasicProperties,
+}
+
+impl PublishedMessage {
+  pub fn message(&self) -> String {
+    String::from_utf8_lossy(&self.payload).to_string()
+  }
+}
+
+/// Keeps the messages in memory, used when the worker runs without a broker channel.
+#[derive(Default)]
+pub struct MemoryPublisher {
+  messages: Mutex<Vec<PublishedMessage>>,
+}
+
+impl MemoryPublisher {
+  pub fn messages(&self) -> Vec<PublishedMessage> {
+    self
+      .messages
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .clone()
+  }
+}
+
+impl MessagePublisher for MemoryPublisher {
+  fn publish_response(
+    &self,
+    routing_key: &str,
+    payload: Vec<u8>,
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    self
+      .messages
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .push(PublishedMessage {
+        routing_key: routing_key.to_string(),
+        payload,
+        properties,
+      });
+    Ok(())
+  }
+
+  fn publish_to_queue(
+    &self,
+    queue: &str,
+    message: String,
+    properties: BasicProperties,
+  ) -> Result<(), String> {
+    self.publish_response(queue, message.into_bytes(), properties)
+  }
+}
+
+/// Liveness ping, distinct from the job progression.
+pub fn publish_job_heartbeat(publisher: &dyn MessagePublisher, job: &Job) -> Result<(), String> {
+  let message = json!({
+    "job_id": job.job_id,
+    "heartbeat": true,
+  });
+
+  publisher.publish("job_heartbeat", message.to_string())
+}
+
+/// Result published while the job is running, superseded by the next one and by the final result.
+pub fn publish_intermediate_result(
+  publisher: &dyn MessagePublisher,
+  result: Value,
+) -> Result<(), String> {
+  publisher.publish("job_intermediate_result", result.to_string())
+}
+
+fn gzip(contents: &str) -> std::io::Result<Vec<u8>> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(contents.as_bytes())?;
+  encoder.finish()
+}
+
+/// Results larger than the threshold are gzip compressed, with the `gzip` content encoding.
+pub fn publish_job_result(
+  publisher: &dyn MessagePublisher,
+  routing_key: &str,
+  message: String,
+  compress_threshold: Option<usize>,
+) -> Result<(), String> {
+  if let Some(threshold) = compress_threshold {
+    if message.len() > threshold {
+      match gzip(&message) {
+        Ok(payload) => {
+          let properties = BasicProperties::default().with_content_encoding("gzip".into());
+          return publisher.publish_response(routing_key, payload, properties);
+        }
+        Err(error) => warn!("unable to compress the job result: {}", error),
+      }
+    }
+  }
+  publisher.publish(routing_key, message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::read::GzDecoder;
+  use std::io::Read;
+
+  #[test]
+  fn wait_with_timeout_gives_up_on_a_pending_future() {
+    let timeout = Duration::from_millis(50);
+    assert_eq!(wait_with_timeout(futures::empty::<(), ()>(), timeout), None);
+
+    let (sender, receiver) = futures::sync::oneshot::channel();
+    thread::spawn(move || {
+      thread::sleep(Duration::from_millis(10));
+      let _ = sender.send(262);
+    });
+    assert_eq!(
+      wait_with_timeout(receiver, Duration::from_secs(5)),
+      Some(Ok(262))
+    );
+  }
+
+  #[test]
+  fn large_job_result_is_published_compressed() {
+    let publisher = MemoryPublisher::default();
+    let small = json!({"job_id": 242, "status": "completed"}).to_string();
+    let large =
+      json!({"job_id": 242, "status": "completed", "message": "x".repeat(1000)}).to_string();
+
+    publish_job_result(&publisher, "job_completed", small.clone(), Some(100)).unwrap();
+    publish_job_result(&publisher, "job_completed", large.clone(), Some(100)).unwrap();
+
+    let messages = publisher.messages();
+    assert_eq!(messages[0].message(), small);
+    assert_eq!(messages[0].properties.content_encoding(), &None);
+
+    assert!(messages[1].payload.len() < large.len());
+    assert_eq!(
+      messages[1].properties.content_encoding(),
+      &Some("gzip".into())
+    );
+    let mut decompressed = String::new();
+    GzDecoder::new(messages[1].payload.as_slice())
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, large);
+  }
+}
@@ -0,0 +1,33 @@
+use crate::helpers::{get_parameter_value, json_to_py};
+use amqp_worker::job::Job;
+use pyo3::{prelude::*, types::PyDict};
+use serde_json::{json, Value};
+
+/// Reserved parameter carrying the expected output format of the job.
+const OUTPUT_PROFILE_PARAMETER: &str = "_output_profile";
+
+/// Key of the output profile in the parameters given to `process`.
+const OUTPUT_PROFILE_KEY: &str = "output_profile";
+
+/// The profile is given either as a JSON object (e.g. `{"format": "mp4", "video_codec": "h264"}`)
+/// or as a profile name, passed as `{"name": ...}`.
+fn parse_output_profile(value: &str) -> Value {
+  match serde_json::from_str(value) {
+    Ok(Value::Object(profile)) => Value::Object(profile),
+    _ => json!({ "name": value }),
+  }
+}
+
+/// Adds the `output_profile` dict to the parameters, unless the worker declares an explicit
+/// `output_profile` parameter, which takes precedence.
+pub fn set_output_profile(py: Python, job: &Job, parameters: &PyDict) -> PyResult<()> {
+  if parameters.get_item(OUTPUT_PROFILE_KEY).is_some() {
+    return Ok(());
+  }
+
+  if let Some(value) = get_parameter_value(job, OUTPUT_PROFILE_PARAMETER) {
+    let profile = parse_output_profile(&value);
+    parameters.set_item(OUTPUT_PROFILE_KEY, json_to_py(py, &profile))?;
+  }
+  Ok(())
+}
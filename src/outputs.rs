@@ -0,0 +1,249 @@
+use crate::helpers::{py_to_json_with_options, Destination, JsonOptions};
+use amqp_worker::Parameter::{self, *};
+use pyo3::{prelude::*, types::*};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+  fs::{self, File},
+  io,
+};
+
+fn sha256_digest(path: &str) -> io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  io::copy(&mut file, &mut hasher)?;
+  Ok(format!("{:x}", hasher.result()))
+}
+
+/// SHA-256 digest of each destination path, streamed from the files.
+pub fn get_digests(destination_paths: &[String]) -> Value {
+  let mut digests = Map::new();
+
+  for path in destination_paths {
+    match sha256_digest(path) {
+      Ok(digest) => {
+        digests.insert(path.to_string(), Value::String(digest));
+      }
+      Err(error) => warn!("unable to compute the digest of {}: {}", path, error),
+    }
+  }
+
+  Value::Object(digests)
+}
+
+fn get_size(path: &str) -> u64 {
+  fs::metadata(path)
+    .map(|metadata| metadata.len())
+    .unwrap_or_else(|error| {
+      warn!("unable to read the size of {}: {}", path, error);
+      0
+    })
+}
+
+/// Size in bytes of each destination path, a missing file is reported with a size of 0.
+pub fn get_sizes(destination_paths: &[String]) -> Value {
+  let mut sizes = Map::new();
+
+  for path in destination_paths {
+    sizes.insert(path.to_string(), Value::from(get_size(path)));
+  }
+
+  Value::Object(sizes)
+}
+
+pub fn get_total_size(destination_paths: &[String]) -> u64 {
+  destination_paths.iter().map(|path| get_size(path)).sum()
+}
+
+/// MIME type of each destination path, given by the worker or inferred from the extension.
+pub fn get_mime_types(destinations: &[Destination], infer: bool) -> Map<String, Value> {
+  let mut mime_types = Map::new();
+
+  for destination in destinations {
+    let mime_type = destination.mime_type.clone().or_else(|| {
+      if infer {
+        mime_guess::from_path(&destination.path)
+          .first_raw()
+          .map(|mime_type| mime_type.to_string())
+      } else {
+        None
+      }
+    });
+
+    if let Some(mime_type) = mime_type {
+      mime_types.insert(destination.path.clone(), Value::String(mime_type));
+    }
+  }
+
+  mime_types
+}
+
+/// Keys of the dict returned by `process` handled by the worker.
+const RETURN_KEYS: &[&str] = &["cleanup_paths", "destination_paths", "parameters"];
+
+/// Keys of the dict returned by `process` which are not handled, usually typos.
+pub fn get_unknown_keys(response: &PyAny) -> Vec<String> {
+  response
+    .downcast_ref::<PyDict>()
+    .map(|dict| {
+      dict
+        .keys()
+        .iter()
+        .map(|key| key.to_string())
+        .filter(|key| !RETURN_KEYS.contains(&key.as_str()))
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Output parameter declared by the `get_output_parameters` function of the Python worker.
+pub struct OutputDescription {
+  pub identifier: String,
+  pub kind: String,
+  pub required: bool,
+}
+
+impl OutputDescription {
+  pub fn from_python(object: &PyDict) -> Self {
+    OutputDescription {
+      identifier: object
+        .get_item("identifier")
+        .expect("missing identifier in output parameter")
+        .to_string(),
+      kind: object
+        .get_item("kind")
+        .map(|kind| kind.to_string())
+        .unwrap_or_else(|| "any".to_string()),
+      required: object
+        .get_item("required")
+        .map(|required| required.is_true().unwrap_or(false))
+        .unwrap_or(false),
+    }
+  }
+
+  fn accepts(&self, value: &Value) -> bool {
+    match self.kind.as_str() {
+      "string" => value.is_string(),
+      "integer" => value.is_i64(),
+      "number" => value.is_number(),
+      "boolean" => value.is_boolean(),
+      "array_of_strings" => value
+        .as_array()
+        .map(|items| items.iter().all(Value::is_string))
+        .unwrap_or(false),
+      "array" => value.is_array(),
+      "object" => value.is_object(),
+      _ => true,
+    }
+  }
+}
+
+/// Checks the `parameters` dict returned by `process` against the declared output parameters:
+/// no unexpected parameter, the declared types, and the required parameters.
+pub fn check_output_schema(
+  response: &PyAny,
+  descriptions: &[OutputDescription],
+  options: &JsonOptions,
+) -> Result<(), String> {
+  let parameters = match response
+    .downcast_ref::<PyDict>()
+    .ok()
+    .and_then(|object| object.get_item("parameters"))
+  {
+    Some(parameters) => py_to_json_with_options(parameters, options)?,
+    None => Value::Object(Map::new()),
+  };
+  let parameters = parameters
+    .as_object()
+    .ok_or_else(|| "returned parameters must be a dict".to_string())?;
+
+  let mut errors = vec![];
+  for (id, value) in parameters {
+    match descriptions
+      .iter()
+      .find(|description| description.identifier == *id)
+    {
+      None => errors.push(format!("unexpected output parameter {}", id)),
+      Some(description) if !description.accepts(value) => errors.push(format!(
+        "output parameter {} must be {}, got {}",
+        id, description.kind, value
+      )),
+      Some(_) => {}
+    }
+  }
+  for description in descriptions {
+    if description.required && !parameters.contains_key(&description.identifier) {
+      errors.push(format!(
+        "missing output parameter {}",
+        description.identifier
+      ));
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(format!("invalid output parameters: {}", errors.join(", ")))
+  }
+}
+
+/// Converts the `parameters` dict returned by `process` to job result parameters.
+/// Strings, booleans, integers and lists of strings keep their type, other values are serialized as JSON strings.
+pub fn get_output_parameters(
+  response: &PyAny,
+  options: &JsonOptions,
+) -> Result<Vec<Parameter>, String> {
+  let parameters = match response
+    .downcast_ref::<PyDict>()
+    .ok()
+    .and_then(|object| object.get_item("parameters"))
+  {
+    Some(parameters) => parameters
+      .downcast_ref::<PyDict>()
+      .map_err(|_| "returned parameters must be a dict".to_string())?,
+    None => return Ok(vec![]),
+  };
+
+  let mut output_parameters = vec![];
+  for (key, value) in parameters.iter() {
+    let id = key.to_string();
+    let value = py_to_json_with_options(value, options)
+      .map_err(|error| format!("invalid value for output parameter {}: {}", id, error))?;
+
+    let parameter = match value {
+      Value::String(value) => StringParam {
+        id,
+        default: None,
+        value: Some(value),
+      },
+      Value::Bool(value) => BooleanParam {
+        id,
+        default: None,
+        value: Some(value),
+      },
+      Value::Number(ref number) if number.is_i64() => IntegerParam {
+        id,
+        default: None,
+        value: number.as_i64(),
+      },
+      Value::Array(ref items) if items.iter().all(Value::is_string) => ArrayOfStringsParam {
+        id,
+        default: None,
+        value: Some(
+          items
+            .iter()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect(),
+        ),
+      },
+      value => StringParam {
+        id,
+        default: None,
+        value: Some(value.to_string()),
+      },
+    };
+    output_parameters.push(parameter);
+  }
+
+  Ok(output_parameters)
+}
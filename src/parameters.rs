@@ -0,0 +1,281 @@
+use crate::helpers::py_to_json;
+use amqp_worker::worker::{Parameter, ParameterType};
+use pyo3::{prelude::*, types::*};
+use serde_json::{json, Value};
+
+/// Parameter as declared by the `get_parameters` function of the Python worker.
+#[derive(Clone, Debug, Default)]
+pub struct ParameterDescription {
+  pub identifier: String,
+  pub label: String,
+  pub kind: Vec<String>,
+  pub required: bool,
+  pub unit: Option<String>,
+  /// Names and integer codes of an `enum_int` parameter.
+  pub enum_values: Vec<(String, i64)>,
+  /// Pass the name of an `enum_int` value to Python, instead of its code.
+  pub pass_name: bool,
+  /// Item type of a `typed_array` parameter.
+  pub dtype: Option<String>,
+  /// Group of mutually exclusive parameters, at most one of them can be given.
+  pub oneof: Option<String>,
+  /// Reject the values whose type does not match the kind, instead of coercing them.
+  pub strict: bool,
+  /// Fields of a composite (JSON object) credential to redact, the whole value when not declared.
+  pub sensitive_fields: Option<Vec<String>>,
+  /// Default value, only used to check the declaration.
+  pub default: Option<Value>,
+  /// Parameters which must be given with this one, with their expected value when declared.
+  pub requires: Vec<(String, Option<Value>)>,
+  /// Parameters which cannot be given with this one.
+  pub conflicts: Vec<String>,
+}
+
+impl ParameterDescription {
+  pub fn from_python(py: Python, object: &PyDict) -> Self {
+    let label = object
+      .get_item("label")
+      .expect("missing label in parameter")
+      .to_string();
+    let identifier = object
+      .get_item("identifier")
+      .expect("missing identifier in parameter")
+      .to_string();
+
+    let kind_list = object
+      .get_item("kind")
+      .expect("missing kind in parameter")
+      .downcast_ref::<PyList>()
+      .unwrap();
+
+    let mut kind = vec![];
+
+    for item in kind_list.iter() {
+      let value = item
+        .downcast_ref::<PyString>()
+        .expect("not a python string")
+        .to_string()
+        .unwrap();
+      kind.push(value.to_string());
+    }
+
+    let required = object
+      .get_item("required")
+      .unwrap_or_else(|| PyBool::new(py, false).as_ref())
+      .is_true()
+      .unwrap();
+
+    let unit = object
+      .get_item("unit")
+      .filter(|unit| !unit.is_none())
+      .map(|unit| unit.to_string());
+
+    let mut enum_values = vec![];
+    if let Some(values) = object.get_item("values") {
+      let values = values
+        .downcast_ref::<PyDict>()
+        .expect("values of an enum_int parameter must be a dict");
+      for (name, code) in values.iter() {
+        let code = code
+          .extract::<i64>()
+          .unwrap_or_else(|_| panic!("code of {} must be an integer", name));
+        enum_values.push((name.to_string(), code));
+      }
+    }
+
+    let pass_name = object
+      .get_item("pass_name")
+      .map(|pass_name| pass_name.is_true().unwrap_or(false))
+      .unwrap_or(false);
+
+    let dtype = object
+      .get_item("dtype")
+      .filter(|dtype| !dtype.is_none())
+      .map(|dtype| dtype.to_string());
+
+    let oneof = object
+      .get_item("oneof")
+      .filter(|oneof| !oneof.is_none())
+      .map(|oneof| oneof.to_string());
+
+    let strict = object
+      .get_item("strict")
+      .map(|strict| strict.is_true().unwrap_or(false))
+      .unwrap_or(false);
+
+    let sensitive_fields = object
+      .get_item("sensitive_fields")
+      .filter(|fields| !fields.is_none())
+      .map(|fields| {
+        fields
+          .extract::<Vec<String>>()
+          .expect("sensitive_fields must be a list of strings")
+      });
+
+    let default = object
+      .get_item("default")
+      .filter(|default| !default.is_none())
+      .map(|default| {
+        py_to_json(default)
+          .unwrap_or_else(|error| panic!("invalid default of {}: {}", identifier, error))
+      });
+
+    // either a list of identifiers, or a dict of identifiers and expected values
+    let mut requires = vec![];
+    if let Some(declared) = object.get_item("requires") {
+      if let Ok(declared) = declared.downcast_ref::<PyDict>() {
+        for (required, value) in declared.iter() {
+          let value = py_to_json(value)
+            .unwrap_or_else(|error| panic!("invalid requires of {}: {}", identifier, error));
+          requires.push((required.to_string(), Some(value)));
+        }
+      } else {
+        let declared: Vec<String> = declared
+          .extract()
+          .unwrap_or_else(|_| panic!("requires of {} must be a list or a dict", identifier));
+        requires.extend(declared.into_iter().map(|required| (required, None)));
+      }
+    }
+
+    let conflicts = object
+      .get_item("conflicts")
+      .map(|conflicts| {
+        conflicts
+          .extract::<Vec<String>>()
+          .unwrap_or_else(|_| panic!("conflicts of {} must be a list of identifiers", identifier))
+      })
+      .unwrap_or_default();
+
+    ParameterDescription {
+      identifier,
+      label,
+      kind,
+      required,
+      unit,
+      enum_values,
+      pass_name,
+      dtype,
+      oneof,
+      strict,
+      sensitive_fields,
+      default,
+      requires,
+      conflicts,
+    }
+  }
+
+  /// Whether the default value has the type expected by the kind.
+  pub fn is_default_compatible(&self) -> bool {
+    let default = match &self.default {
+      Some(default) => default,
+      None => return true,
+    };
+    let is_list_of_strings = |value: &Value| {
+      value
+        .as_array()
+        .map(|items| items.iter().all(Value::is_string))
+        .unwrap_or(false)
+    };
+
+    // with several kinds, the default must match one of them
+    self.kind.iter().any(|kind| match kind.as_str() {
+      "boolean" => default.is_boolean(),
+      "integer" => default.is_i64(),
+      "array_of_strings" | "paths" => is_list_of_strings(default),
+      "enum_int" => self.enum_values.iter().any(|(name, code)| {
+        default.as_str() == Some(name.as_str()) || default.as_i64() == Some(*code)
+      }),
+      "requirement" => true,
+      "timerange" => default.is_object(),
+      _ => default.is_string(),
+    })
+  }
+
+  pub fn has_kind(&self, kind: &str) -> bool {
+    self.kind.iter().any(|item| item == kind)
+  }
+
+  pub fn to_json(&self) -> Value {
+    json!({
+      "identifier": self.identifier,
+      "label": self.label,
+      "kind": self.kind,
+      "required": self.required,
+      "unit": self.unit,
+      "dtype": self.dtype,
+      "oneof": self.oneof,
+      "strict": self.strict,
+      "sensitive_fields": self.sensitive_fields,
+      "requires": self.requires.iter().map(|(required, _)| required).collect::<Vec<&String>>(),
+      "conflicts": self.conflicts,
+      "values": self.enum_values.iter().cloned().collect::<std::collections::BTreeMap<String, i64>>(),
+    })
+  }
+
+  /// A required group of exclusive parameters is not required parameter by parameter.
+  pub fn to_worker_parameter(&self) -> Parameter {
+    Parameter {
+      label: self.label.clone(),
+      identifier: self.identifier.clone(),
+      kind: self
+        .kind
+        .iter()
+        .map(|kind| get_parameter_type(kind))
+        .collect(),
+      required: self.required && self.oneof.is_none(),
+    }
+  }
+
+  /// The AMQP worker parameter has no unit, it is added as its own field when declared.
+  pub fn to_worker_parameter_json(&self) -> Value {
+    let mut parameter = serde_json::to_value(self.to_worker_parameter()).unwrap();
+    if let (Some(unit), Value::Object(fields)) = (&self.unit, &mut parameter) {
+      fields.insert("unit".to_string(), json!(unit));
+    }
+    parameter
+  }
+}
+
+pub fn find_description<'a>(
+  descriptions: &'a [ParameterDescription],
+  identifier: &str,
+) -> Option<&'a ParameterDescription> {
+  descriptions
+    .iter()
+    .find(|description| description.identifier == identifier)
+}
+
+/// Kinds handled by this worker are transported to the orchestrator with a base type.
+fn get_parameter_type(kind: &str) -> ParameterType {
+  match kind {
+    "color" | "enum_int" | "path" | "secret_ref" | "timerange" | "typed_array" => {
+      ParameterType::String
+    }
+    "paths" => ParameterType::ArrayOfStrings,
+    _ => serde_json::from_str(&format!("{:?}", kind))
+      .unwrap_or_else(|_| panic!("unsupported parameter kind: {}", kind)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn requirement_accepts_any_default() {
+    let description = ParameterDescription {
+      identifier: "requirements".to_string(),
+      label: "Requirements".to_string(),
+      kind: vec!["requirement".to_string()],
+      default: Some(json!({"paths": ["/data/model.bin"]})),
+      ..Default::default()
+    };
+    assert!(description.is_default_compatible());
+
+    let description = ParameterDescription {
+      kind: vec!["string".to_string()],
+      ..description
+    };
+    assert!(!description.is_default_compatible());
+  }
+}
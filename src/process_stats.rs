@@ -0,0 +1,58 @@
+use std::{fs, process};
+
+/// Threads and child processes of the worker, read from `/proc`.
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessSample {
+  pub threads: usize,
+  pub children: usize,
+}
+
+/// `None` when `/proc` is not available.
+pub fn sample() -> Option<ProcessSample> {
+  let threads = fs::read_dir("/proc/self/task").ok()?.count();
+
+  let pid = process::id().to_string();
+  let children = fs::read_dir("/proc")
+    .ok()?
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| {
+      entry
+        .file_name()
+        .to_str()
+        .map(|name| name.chars().all(|character| character.is_ascii_digit()))
+        .unwrap_or(false)
+    })
+    .filter_map(|entry| fs::read_to_string(entry.path().join("stat")).ok())
+    .filter(|stat| get_parent_pid(stat) == Some(pid.as_str()))
+    .count();
+
+  Some(ProcessSample { threads, children })
+}
+
+/// The command name may contain spaces, the fields are read after its closing parenthesis.
+fn get_parent_pid(stat: &str) -> Option<&str> {
+  let fields = &stat[stat.rfind(')')? + 1..];
+  fields.split_whitespace().nth(1)
+}
+
+/// Logs the threads and child processes still running after the job, compared to before it.
+pub fn check_leaks(job_id: u64, before: Option<ProcessSample>, after: Option<ProcessSample>) {
+  let (before, after) = match (before, after) {
+    (Some(before), Some(after)) => (before, after),
+    _ => return,
+  };
+
+  debug!(
+    "job {}: {} threads and {} child processes before process, {} and {} after",
+    job_id, before.threads, before.children, after.threads, after.children
+  );
+
+  if after.threads > before.threads || after.children > before.children {
+    warn!(
+      "job {} may leak resources: {} more threads and {} more child processes after process",
+      job_id,
+      after.threads.saturating_sub(before.threads),
+      after.children.saturating_sub(before.children)
+    );
+  }
+}
@@ -0,0 +1,66 @@
+use crate::{config, describe, parameters::ParameterDescription, PythonWorkerEvent};
+use serde_json::{json, Map, Value};
+use std::{fs, time::Duration};
+
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Registration payload of the worker, built from the `--describe` descriptor.
+/// The parameters are given with the kinds transported to the orchestrator.
+pub fn get_registration_payload(event: &PythonWorkerEvent) -> Value {
+  let mut fields = match describe::get_descriptor(event) {
+    Value::Object(fields) => fields,
+    _ => Map::new(),
+  };
+
+  let parameters: Vec<Value> = event
+    .get_parameters_description()
+    .iter()
+    .map(ParameterDescription::to_worker_parameter_json)
+    .collect();
+  fields.insert("parameters".to_string(), json!(parameters));
+  fields.insert("queue_name".to_string(), json!(config::queue_name()));
+
+  match config::registry_template() {
+    Some(filename) => {
+      let template = fs::read_to_string(&filename)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_else(|| panic!("unable to read the registration template from {}", filename));
+      apply_template(&template, &fields)
+    }
+    None => Value::Object(fields),
+  }
+}
+
+/// Replaces the `"$<field>"` strings of the template by the value of the field.
+fn apply_template(template: &Value, fields: &Map<String, Value>) -> Value {
+  match template {
+    Value::String(value) if value.starts_with('$') => fields
+      .get(&value[1..])
+      .cloned()
+      .unwrap_or_else(|| panic!("unknown field {} in the registration template", value)),
+    Value::Array(items) => Value::Array(
+      items
+        .iter()
+        .map(|item| apply_template(item, fields))
+        .collect(),
+    ),
+    Value::Object(object) => Value::Object(
+      object
+        .iter()
+        .map(|(key, value)| (key.clone(), apply_template(value, fields)))
+        .collect(),
+    ),
+    value => value.clone(),
+  }
+}
+
+pub fn register(url: &str, payload: &Value) -> Result<(), String> {
+  reqwest::Client::builder()
+    .timeout(REGISTRATION_TIMEOUT)
+    .build()
+    .and_then(|client| client.post(url).json(payload).send())
+    .and_then(|response| response.error_for_status())
+    .map(|_| ())
+    .map_err(|error| error.to_string())
+}
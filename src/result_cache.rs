@@ -0,0 +1,53 @@
+use crate::{cache::LruCache, config, describe, helpers::get_missing_paths};
+use amqp_worker::job::{Job, JobResult};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+lazy_static! {
+  static ref RESULT_CACHE: Mutex<LruCache> = Mutex::new(LruCache::new(config::cache_size()));
+}
+
+/// Identical parameters processed by the same Python source give the same result.
+pub fn get_key(job: &Job, contents: &str) -> String {
+  let parameters = serde_json::to_string(&job.parameters).unwrap_or_default();
+  let digest =
+    Sha256::digest(format!("{}:{}", describe::get_source_hash(contents), parameters).as_bytes());
+  format!("{:x}", digest)
+}
+
+/// Cached result of an identical job, `None` when one of its destination paths no longer exists.
+pub fn get(key: &str, job: &Job) -> Option<JobResult> {
+  let cached = RESULT_CACHE
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .get(key)?;
+  let mut cached: Value = serde_json::from_str(&cached).ok()?;
+
+  let destination_paths: Vec<String> =
+    serde_json::from_value(cached["destination_paths"].take()).unwrap_or_default();
+  let missing_paths = get_missing_paths(&destination_paths);
+  if !missing_paths.is_empty() {
+    info!(
+      "cached result ignored, missing destination paths: {}",
+      missing_paths.join(", ")
+    );
+    return None;
+  }
+
+  let mut result = cached["result"].take();
+  result["job_id"] = json!(job.job_id);
+  serde_json::from_value(result).ok()
+}
+
+pub fn set(key: &str, job_result: &JobResult, destination_paths: &[String]) {
+  let cached = json!({
+    "result": job_result,
+    "destination_paths": destination_paths,
+  });
+
+  RESULT_CACHE
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .set(key, &cached.to_string(), config::result_cache_ttl());
+}
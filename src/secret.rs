@@ -0,0 +1,173 @@
+use crate::{config, validation::ValidationError};
+use amqp_worker::{job::Job, Credential};
+use serde_json::Value;
+use std::{
+  sync::{mpsc, Arc, Mutex},
+  thread,
+};
+
+lazy_static! {
+  static ref SECRET_RESOLVERS: Mutex<Vec<Arc<dyn SecretResolver>>> = Mutex::new(vec![]);
+}
+
+/// Requests a credential value, giving up after `PYTHON_WORKER_CREDENTIAL_TIMEOUT` when the backend is slow.
+/// In local mode (`PYTHON_WORKER_SKIP_CREDENTIALS`), the key itself is returned.
+pub fn request_credential(key: &str, job: &Job) -> Result<String, String> {
+  if config::skip_credentials() {
+    warn!(
+      "credentials are not resolved (PYTHON_WORKER_SKIP_CREDENTIALS), the key {} is passed as the value",
+      key
+    );
+    return Ok(key.to_string());
+  }
+
+  let timeout = match config::credential_timeout() {
+    Some(timeout) => timeout,
+    None => {
+      let credential = Credential {
+        key: key.to_string(),
+      };
+      return credential
+        .request_value(job)
+        .map_err(|_| format!("unable to retrieve the credential value for {}", key));
+    }
+  };
+
+  let (sender, receiver) = mpsc::channel();
+  let credential = Credential {
+    key: key.to_string(),
+  };
+  let job = job.clone();
+  thread::spawn(move || {
+    let _ = sender.send(credential.request_value(&job).ok());
+  });
+
+  match receiver.recv_timeout(timeout) {
+    Ok(Some(value)) => Ok(value),
+    Ok(None) => Err(format!(
+      "unable to retrieve the credential value for {}",
+      key
+    )),
+    Err(_) => Err(format!(
+      "timeout after {}s retrieving the credential value for {}",
+      timeout.as_secs(),
+      key
+    )),
+  }
+}
+
+/// Resolves a secret reference (e.g. `vault://path#field`) to its value.
+pub trait SecretResolver: Send + Sync {
+  fn handles(&self, reference: &str) -> bool;
+  fn resolve(&self, reference: &str, job: &Job) -> Result<String, String>;
+}
+
+/// Default resolver of the `credential://` references and of the plain keys, using the reference
+/// (without the scheme) as a credential key.
+pub struct CredentialResolver {}
+
+impl SecretResolver for CredentialResolver {
+  fn handles(&self, reference: &str) -> bool {
+    reference.starts_with("credential://") || !reference.contains(':')
+  }
+
+  fn resolve(&self, reference: &str, job: &Job) -> Result<String, String> {
+    request_credential(reference.trim_start_matches("credential://"), job)
+  }
+}
+
+/// Resolvers are tried in the order they are registered.
+pub fn register_resolver(resolver: Arc<dyn SecretResolver>) {
+  SECRET_RESOLVERS
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .push(resolver);
+}
+
+pub fn resolve_secret(reference: &str, job: &Job) -> Result<String, String> {
+  // the lock is not held while resolving, as the backend can be slow
+  let resolver = SECRET_RESOLVERS
+    .lock()
+    .unwrap_or_else(|error| error.into_inner())
+    .iter()
+    .find(|resolver| resolver.handles(reference))
+    .cloned()
+    .ok_or_else(|| format!("no resolver for secret reference {}", reference))?;
+  resolver.resolve(reference, job)
+}
+
+/// Values which must never appear in job messages.
+#[derive(Default)]
+pub struct SecretValues {
+  values: Vec<String>,
+}
+
+impl SecretValues {
+  pub fn add(&mut self, value: &str) {
+    if !value.is_empty() {
+      self.values.push(value.to_string());
+    }
+  }
+
+  /// A composite credential (a JSON object) with declared sensitive fields only hides these fields,
+  /// the other ones (e.g. a user name) stay readable.
+  pub fn add_credential(&mut self, value: &str, sensitive_fields: Option<&[String]>) {
+    let fields = match (sensitive_fields, serde_json::from_str::<Value>(value)) {
+      (Some(fields), Ok(Value::Object(object))) => fields
+        .iter()
+        .filter_map(|field| object.get(field))
+        .map(|field| match field {
+          Value::String(field) => field.clone(),
+          field => field.to_string(),
+        })
+        .collect::<Vec<String>>(),
+      _ => return self.add(value),
+    };
+
+    for field in fields {
+      self.add(&field);
+    }
+  }
+
+  pub fn redact(&self, message: &str) -> String {
+    self
+      .values
+      .iter()
+      .fold(message.to_string(), |message, value| {
+        message.replace(value, "********")
+      })
+  }
+
+  /// Invalid values are quoted by the validation messages, they can contain secrets.
+  pub fn redact_validation_errors(&self, errors: Vec<ValidationError>) -> Vec<ValidationError> {
+    errors
+      .into_iter()
+      .map(|error| ValidationError {
+        message: self.redact(&error.message),
+        ..error
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unknown_reference_schemes_are_rejected() {
+    register_resolver(Arc::new(CredentialResolver {}));
+    let job = Job::new(r#"{"job_id": 205, "parameters": []}"#).unwrap();
+
+    let error = resolve_secret("vault://secret/data/db#password", &job).unwrap_err();
+    assert_eq!(
+      error,
+      "no resolver for secret reference vault://secret/data/db#password"
+    );
+    assert!(resolve_secret("arn:aws:secretsmanager:eu-west-1:123:secret:db", &job).is_err());
+
+    let resolver = CredentialResolver {};
+    assert!(resolver.handles("credential://DB_PASSWORD"));
+    assert!(resolver.handles("DB_PASSWORD"));
+  }
+}
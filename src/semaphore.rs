@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+
+/// Limits the number of holders per key, each key having its own count.
+#[derive(Default)]
+pub struct KeyedSemaphore {
+  holders: Mutex<HashMap<String, usize>>,
+  released: Condvar,
+}
+
+pub struct KeyedSemaphoreGuard<'a> {
+  semaphore: &'a KeyedSemaphore,
+  key: String,
+}
+
+impl KeyedSemaphore {
+  pub fn acquire(&self, key: &str, limit: usize) -> KeyedSemaphoreGuard<'_> {
+    let mut holders = self
+      .holders
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+
+    while holders.get(key).cloned().unwrap_or(0) >= limit.max(1) {
+      holders = self
+        .released
+        .wait(holders)
+        .unwrap_or_else(|error| error.into_inner());
+    }
+
+    *holders.entry(key.to_string()).or_insert(0) += 1;
+
+    KeyedSemaphoreGuard {
+      semaphore: self,
+      key: key.to_string(),
+    }
+  }
+}
+
+impl<'a> Drop for KeyedSemaphoreGuard<'a> {
+  fn drop(&mut self) {
+    let mut holders = self
+      .semaphore
+      .holders
+      .lock()
+      .unwrap_or_else(|error| error.into_inner());
+
+    if let Some(count) = holders.get_mut(&self.key) {
+      *count -= 1;
+      if *count == 0 {
+        holders.remove(&self.key);
+      }
+    }
+
+    self.semaphore.released.notify_all();
+  }
+}
@@ -0,0 +1,60 @@
+use crate::{config, interpreter};
+use pyo3::{prelude::*, types::PyDict};
+
+/// Functions of the worker module, with their number of positional arguments and whether they are required.
+const FUNCTIONS: &[(&str, usize, bool)] = &[
+  ("get_name", 0, false),
+  ("get_short_description", 0, true),
+  ("get_description", 0, true),
+  ("get_version", 0, false),
+  ("get_parameters", 0, true),
+  ("get_capabilities", 0, false),
+  ("get_output_parameters", 0, false),
+  ("is_reentrant", 0, false),
+  ("preprocess", 1, false),
+  ("process", 2, true),
+  ("upload", 2, false),
+];
+
+/// Checks that the worker module defines the required functions, with compatible signatures.
+pub fn check_signatures(contents: &str) -> Vec<String> {
+  let gil = Python::acquire_gil();
+  let py = gil.python();
+  let module = match interpreter::load_module(py, contents, false) {
+    Ok(module) => module,
+    Err(error) => {
+      error.print(py);
+      return vec![format!("unable to load {}", config::filename())];
+    }
+  };
+
+  let mut mismatches = vec![];
+  for (name, arity, required) in FUNCTIONS {
+    if !module.hasattr(*name).unwrap_or(false) {
+      if *required {
+        mismatches.push(format!("{} is not defined", name));
+      }
+      continue;
+    }
+
+    let locals = PyDict::new(py);
+    let bound = locals
+      .set_item("function", module.get(name).unwrap())
+      .and_then(|()| locals.set_item("arity", arity))
+      .and_then(|()| {
+        py.run(
+          "import inspect\ninspect.signature(function).bind(*([None] * arity))",
+          None,
+          Some(locals),
+        )
+      });
+
+    if bound.is_err() {
+      mismatches.push(format!(
+        "{} must accept {} positional argument(s)",
+        name, arity
+      ));
+    }
+  }
+  mismatches
+}
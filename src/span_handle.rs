@@ -0,0 +1,32 @@
+use crate::telemetry::{self, ChildSpan};
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+/// Span of a sub-operation, returned to Python by `start_span`.
+#[pyclass]
+pub struct SpanHandle {
+  span: Mutex<Option<ChildSpan>>,
+}
+
+impl SpanHandle {
+  pub fn start(name: &str) -> Self {
+    SpanHandle {
+      span: Mutex::new(Some(telemetry::start_child_span(name))),
+    }
+  }
+}
+
+#[pymethods]
+impl SpanHandle {
+  /// Ending a span twice has no effect.
+  fn end(&self) {
+    if let Some(span) = self
+      .span
+      .lock()
+      .unwrap_or_else(|error| error.into_inner())
+      .take()
+    {
+      span.end();
+    }
+  }
+}
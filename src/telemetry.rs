@@ -0,0 +1,132 @@
+use amqp_worker::job::Job;
+use std::time::Instant;
+
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::api::{KeyValue, NoopProvider, Provider, Span, Tracer};
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::global;
+
+#[cfg(feature = "opentelemetry")]
+const TRACER_NAME: &str = "py_amqp_worker";
+
+/// Span covering the processing of one job, ended when dropped.
+pub struct JobSpan {
+  #[cfg(feature = "opentelemetry")]
+  span: global::BoxedSpan,
+}
+
+#[cfg(feature = "opentelemetry")]
+pub fn start_job_span(job: &Job) -> JobSpan {
+  let tracer = global::trace_provider().get_tracer(TRACER_NAME);
+  let mut span = tracer.start("process", None);
+  let job_id = job.job_id.to_string();
+  span.set_attribute(KeyValue::new("job_id", job_id.as_str()));
+  tracer.mark_span_as_active(&span);
+  JobSpan { span }
+}
+
+#[cfg(not(feature = "opentelemetry"))]
+pub fn start_job_span(_job: &Job) -> JobSpan {
+  JobSpan {}
+}
+
+#[cfg(feature = "opentelemetry")]
+impl Drop for JobSpan {
+  fn drop(&mut self) {
+    self.span.end();
+  }
+}
+
+#[cfg(feature = "opentelemetry")]
+pub fn add_event(message: String) {
+  let tracer = global::trace_provider().get_tracer(TRACER_NAME);
+  tracer.get_active_span().add_event(message);
+}
+
+#[cfg(not(feature = "opentelemetry"))]
+pub fn add_event(_message: String) {}
+
+/// Flushes the recorded spans, replacing the provider drops it along with its exporter.
+#[cfg(feature = "opentelemetry")]
+pub fn shutdown() {
+  global::set_provider(NoopProvider {});
+}
+
+#[cfg(not(feature = "opentelemetry"))]
+pub fn shutdown() {}
+
+/// Flushes the telemetry when the worker is stopped by SIGTERM or SIGINT, then exits.
+/// The signals are blocked before any other thread is started, so they are all received by this thread.
+pub fn shutdown_on_signal() {
+  let signals = unsafe {
+    let mut signals: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut signals);
+    libc::sigaddset(&mut signals, libc::SIGTERM);
+    libc::sigaddset(&mut signals, libc::SIGINT);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+    signals
+  };
+
+  std::thread::spawn(move || {
+    let mut signal = 0;
+    if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+      error!("unable to wait for the stop signals");
+      return;
+    }
+    info!("stopped by signal {}", signal);
+    shutdown();
+    std::process::exit(128 + signal);
+  });
+}
+
+/// Flushes the telemetry when the worker stops on a fatal error, before the default panic report.
+pub fn flush_on_panic() {
+  let default_hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |panic_info| {
+    // a panic in another thread does not stop the worker
+    if std::thread::current().name() == Some("main") {
+      shutdown();
+    }
+    default_hook(panic_info);
+  }));
+}
+
+/// Span of a sub-operation of the job, started from Python.
+pub struct ChildSpan {
+  name: String,
+  started: Instant,
+  #[cfg(feature = "opentelemetry")]
+  span: global::BoxedSpan,
+}
+
+#[cfg(feature = "opentelemetry")]
+pub fn start_child_span(name: &str) -> ChildSpan {
+  let tracer = global::trace_provider().get_tracer(TRACER_NAME);
+  let parent = tracer.get_active_span().get_context();
+  ChildSpan {
+    name: name.to_string(),
+    started: Instant::now(),
+    span: tracer.start(name, Some(parent)),
+  }
+}
+
+#[cfg(not(feature = "opentelemetry"))]
+pub fn start_child_span(name: &str) -> ChildSpan {
+  ChildSpan {
+    name: name.to_string(),
+    started: Instant::now(),
+  }
+}
+
+impl ChildSpan {
+  #[allow(unused_mut)]
+  pub fn end(mut self) {
+    debug!(
+      "span {} ended after {:.3}s",
+      self.name,
+      self.started.elapsed().as_secs_f64()
+    );
+    #[cfg(feature = "opentelemetry")]
+    self.span.end();
+  }
+}
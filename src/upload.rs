@@ -0,0 +1,39 @@
+use crate::config;
+use pyo3::{prelude::*, types::PyDict};
+use std::thread;
+
+/// Calls the optional `upload` function after a successful `process`, retried with an exponential
+/// backoff, the last error is returned when all the attempts fail.
+pub fn upload(
+  py: Python,
+  module: &PyModule,
+  destination_paths: &[String],
+  parameters: &PyDict,
+) -> PyResult<()> {
+  if !module.hasattr("upload")? {
+    return Ok(());
+  }
+
+  let retries = config::upload_retries();
+  let mut attempt = 0;
+  loop {
+    let error = match module.call1("upload", (destination_paths.to_vec(), parameters)) {
+      Ok(_) => return Ok(()),
+      Err(error) => error,
+    };
+
+    if attempt >= retries {
+      return Err(error);
+    }
+
+    let delay = config::upload_backoff() * 2u32.pow(attempt.min(10));
+    warn!(
+      "upload failed (attempt {} of {}), retry in {}s",
+      attempt + 1,
+      retries + 1,
+      delay.as_secs()
+    );
+    py.allow_threads(|| thread::sleep(delay));
+    attempt += 1;
+  }
+}
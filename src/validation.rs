@@ -0,0 +1,244 @@
+use crate::{helpers::with_json_parameter, parameters::ParameterDescription};
+use amqp_worker::job::{JobResult, JobStatus};
+use serde_json::{json, Value};
+
+/// Machine-readable reason of a parameter validation failure.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValidationErrorCode {
+  ConflictingParameters,
+  CredentialUnavailable,
+  EnumMismatch,
+  ExclusiveGroup,
+  InvalidValue,
+  MissingRequired,
+  UnmetDependency,
+}
+
+impl ValidationErrorCode {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ValidationErrorCode::ConflictingParameters => "CONFLICTING_PARAMETERS",
+      ValidationErrorCode::CredentialUnavailable => "CREDENTIAL_UNAVAILABLE",
+      ValidationErrorCode::EnumMismatch => "ENUM_MISMATCH",
+      ValidationErrorCode::ExclusiveGroup => "EXCLUSIVE_GROUP",
+      ValidationErrorCode::InvalidValue => "INVALID_VALUE",
+      ValidationErrorCode::MissingRequired => "MISSING_REQUIRED",
+      ValidationErrorCode::UnmetDependency => "UNMET_DEPENDENCY",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+  pub code: ValidationErrorCode,
+  pub parameter: String,
+  pub message: String,
+}
+
+impl ValidationError {
+  pub fn new(code: ValidationErrorCode, parameter: &str, message: String) -> Self {
+    ValidationError {
+      code,
+      parameter: parameter.to_string(),
+      message,
+    }
+  }
+
+  pub fn to_json(&self) -> Value {
+    json!({
+      "code": self.code.as_str(),
+      "parameter": self.parameter,
+      "message": self.message,
+    })
+  }
+}
+
+/// At most one parameter of each `oneof` group can be given, and exactly one when the group is required.
+pub fn check_exclusive_groups<F: Fn(&str) -> bool>(
+  descriptions: &[ParameterDescription],
+  is_provided: F,
+) -> Vec<ValidationError> {
+  let mut groups: Vec<&String> = descriptions
+    .iter()
+    .filter_map(|description| description.oneof.as_ref())
+    .collect();
+  groups.sort();
+  groups.dedup();
+
+  let mut errors = vec![];
+  for group in groups {
+    let members: Vec<&ParameterDescription> = descriptions
+      .iter()
+      .filter(|description| description.oneof.as_ref() == Some(group))
+      .collect();
+    let identifiers = members
+      .iter()
+      .map(|description| description.identifier.as_str())
+      .collect::<Vec<&str>>()
+      .join(", ");
+    let provided: Vec<&str> = members
+      .iter()
+      .map(|description| description.identifier.as_str())
+      .filter(|identifier| is_provided(identifier))
+      .collect();
+
+    if provided.len() > 1 {
+      errors.push(ValidationError::new(
+        ValidationErrorCode::ExclusiveGroup,
+        group,
+        format!(
+          "only one of {} can be given, got {}",
+          identifiers,
+          provided.join(", ")
+        ),
+      ));
+    } else if provided.is_empty() && members.iter().any(|description| description.required) {
+      errors.push(ValidationError::new(
+        ValidationErrorCode::MissingRequired,
+        group,
+        format!("one of {} is required", identifiers),
+      ));
+    }
+  }
+  errors
+}
+
+/// Checks the `requires` and `conflicts` declarations of the given parameters.
+pub fn check_dependencies<F: Fn(&str) -> Option<Value>>(
+  descriptions: &[ParameterDescription],
+  get_value: F,
+) -> Vec<ValidationError> {
+  let mut errors = vec![];
+
+  for description in descriptions {
+    if get_value(&description.identifier).is_none() {
+      continue;
+    }
+
+    for (required, expected) in &description.requires {
+      let unmet = match (get_value(required), expected) {
+        (None, _) => Some(format!("requires {}", required)),
+        (Some(value), Some(expected)) if value != *expected => Some(format!(
+          "requires {} to be {}, got {}",
+          required, expected, value
+        )),
+        _ => None,
+      };
+      if let Some(message) = unmet {
+        errors.push(ValidationError::new(
+          ValidationErrorCode::UnmetDependency,
+          &description.identifier,
+          message,
+        ));
+      }
+    }
+
+    for conflict in &description.conflicts {
+      if get_value(conflict).is_some() {
+        errors.push(ValidationError::new(
+          ValidationErrorCode::ConflictingParameters,
+          &description.identifier,
+          format!("cannot be given with {}", conflict),
+        ));
+      }
+    }
+  }
+  errors
+}
+
+pub fn get_message(errors: &[ValidationError]) -> String {
+  let message = errors
+    .iter()
+    .map(|error| {
+      format!(
+        "{} ({}): {}",
+        error.parameter,
+        error.code.as_str(),
+        error.message
+      )
+    })
+    .collect::<Vec<String>>()
+    .join("\n");
+  format!("invalid parameters:\n{}", message)
+}
+
+/// Fails the job with all the validation errors, listed in the `validation_errors` parameter.
+pub fn get_validation_failure(job_result: JobResult, errors: &[ValidationError]) -> JobResult {
+  let job_result = job_result
+    .with_status(JobStatus::Error)
+    .with_message(&get_message(errors));
+
+  let errors = errors.iter().map(ValidationError::to_json).collect();
+  with_json_parameter(job_result, "validation_errors", &Value::Array(errors))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn grouped_description(identifier: &str, required: bool) -> ParameterDescription {
+    ParameterDescription {
+      identifier: identifier.to_string(),
+      label: identifier.to_string(),
+      kind: vec!["string".to_string()],
+      required,
+      oneof: Some("source".to_string()),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn exclusive_group_accepts_only_one_parameter() {
+    let descriptions = vec![
+      grouped_description("url", true),
+      grouped_description("path", false),
+    ];
+
+    assert!(check_exclusive_groups(&descriptions, |identifier| identifier == "url").is_empty());
+
+    let errors = check_exclusive_groups(&descriptions, |_| true);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ValidationErrorCode::ExclusiveGroup);
+    assert_eq!(errors[0].parameter, "source");
+
+    let errors = check_exclusive_groups(&descriptions, |_| false);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ValidationErrorCode::MissingRequired);
+  }
+
+  #[test]
+  fn dependencies_report_unmet_requirements_and_conflicts() {
+    let descriptions = vec![ParameterDescription {
+      identifier: "crop_width".to_string(),
+      label: "Crop width".to_string(),
+      kind: vec!["integer".to_string()],
+      requires: vec![("crop_enabled".to_string(), Some(json!(true)))],
+      conflicts: vec!["scale".to_string()],
+      ..Default::default()
+    }];
+    let check = |values: Value| {
+      check_dependencies(&descriptions, |identifier| values.get(identifier).cloned())
+    };
+
+    assert!(check(json!({"crop_width": 10, "crop_enabled": true})).is_empty());
+    assert!(check(json!({"scale": 2})).is_empty());
+
+    let errors = check(json!({"crop_width": 10, "crop_enabled": false}));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ValidationErrorCode::UnmetDependency);
+    assert_eq!(
+      errors[0].message,
+      "requires crop_enabled to be true, got false"
+    );
+
+    let errors = check(json!({"crop_width": 10, "scale": 2}));
+    let codes: Vec<ValidationErrorCode> = errors.iter().map(|error| error.code).collect();
+    assert_eq!(
+      codes,
+      vec![
+        ValidationErrorCode::UnmetDependency,
+        ValidationErrorCode::ConflictingParameters
+      ]
+    );
+  }
+}
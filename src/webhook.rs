@@ -0,0 +1,51 @@
+use crate::{config, helpers::get_result_json};
+use amqp_worker::{job::JobResult, MessageError};
+use serde_json::Value;
+use std::{thread, time::Duration};
+
+/// Posts the job result to the completion webhook in the background, a failure never changes the job result.
+pub fn send_completion(url: String, result: &Result<JobResult, MessageError>) {
+  let body = get_result_json(result);
+  thread::spawn(move || post_completion(&url, &body));
+}
+
+fn post_completion(url: &str, body: &Value) {
+  let client = match reqwest::Client::builder()
+    .timeout(config::completion_webhook_timeout())
+    .build()
+  {
+    Ok(client) => client,
+    Err(error) => {
+      warn!("unable to create the completion webhook client: {}", error);
+      return;
+    }
+  };
+
+  let retries = config::completion_webhook_retries();
+
+  for attempt in 0..=retries {
+    if attempt > 0 {
+      thread::sleep(Duration::from_secs(1 << (attempt - 1).min(5)));
+    }
+
+    match client
+      .post(url)
+      .json(body)
+      .send()
+      .and_then(|response| response.error_for_status())
+    {
+      Ok(_) => return,
+      Err(error) => warn!(
+        "unable to post the job result to the completion webhook (attempt {} of {}): {}",
+        attempt + 1,
+        retries + 1,
+        error
+      ),
+    }
+  }
+
+  error!(
+    "the completion webhook {} did not receive the job result",
+    url
+  );
+}
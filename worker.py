@@ -29,6 +29,10 @@ def process(handle_callback, parameters):
 	# raise Exception("my error")
 	print(parameters)
 
+	# ask to process the job again later, e.g. when its input is not available yet
+	if parameters["my_parameter"] == "not ready":
+		handle_callback.defer(30, "the input is not ready")
+
 	# do some stuff here
 
 	# notify the progression (between 0 and 100)